@@ -13,6 +13,7 @@ pub fn get() -> Result<Option<Args>, pico_args::Error> {
   Ok(Some(Args {
     quiet: args.contains(["-q", "--quiet"]),
     just_ast: args.contains("--just-ast"),
+    deny_warnings: args.contains("--deny-warnings"),
     files: args.free()?,
   }))
 }
@@ -20,5 +21,6 @@ pub fn get() -> Result<Option<Args>, pico_args::Error> {
 pub struct Args {
   pub quiet: bool,
   pub just_ast: bool,
+  pub deny_warnings: bool,
   pub files: Vec<String>,
 }
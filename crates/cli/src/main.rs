@@ -6,8 +6,11 @@ mod source;
 use codespan_reporting::diagnostic::{Diagnostic, Label};
 use codespan_reporting::term;
 use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
-use millet_core::{intern, lex, parse, statics};
+use millet_core::intern::StrStore;
+use millet_core::loc::Located;
+use millet_core::{intern, lex, parse, project, statics};
 use std::io::Write as _;
+use std::path::Path;
 
 fn simple<M, T, R>(msg: M, id: T, loc: R) -> Diagnostic<T>
 where
@@ -19,6 +22,16 @@ where
     .with_labels(vec![Label::primary(id, loc)])
 }
 
+fn statics_diagnostic<T>(store: &StrStore, id: T, e: &Located<statics::Error>) -> Diagnostic<T> {
+  let diag = match e.val.severity() {
+    statics::Severity::Error => Diagnostic::error(),
+    statics::Severity::Warning => Diagnostic::warning(),
+  };
+  diag
+    .with_message(e.val.message(store))
+    .with_labels(vec![Label::primary(id, e.loc)])
+}
+
 fn run() -> bool {
   let config = term::Config::default();
   let w = StandardStream::stdout(ColorChoice::Auto);
@@ -32,10 +45,49 @@ fn run() -> bool {
     }
   };
   let mut src = source::SourceMap::new();
-  let mut store = intern::StrStoreMut::new();
+  let mut names = Vec::with_capacity(args.files.len());
+  let mut mlb = None;
+  let num_files = args.files.len();
   for name in args.files {
-    match std::fs::read_to_string(&name) {
-      Ok(s) => src.insert(name, s),
+    let path = Path::new(&name);
+    if !project::is_project_file(path) {
+      names.push(name);
+      continue;
+    }
+    match project::get(path, |p| std::fs::read_to_string(p)) {
+      Ok(proj) => {
+        for e in proj.warnings.iter() {
+          let diag = Diagnostic::warning().with_message(e.message());
+          term::emit(&mut w, &config, &src, &diag).unwrap();
+        }
+        if args.deny_warnings && !proj.warnings.is_empty() {
+          writeln!(&mut w, "reading project failed").unwrap();
+          return false;
+        }
+        names.extend(proj.files.into_iter().map(|x| x.display().to_string()));
+        if proj.mlb.is_some() {
+          if num_files != 1 {
+            let msg = format!("{}: an MLB file must be the only file given", name);
+            let diag = Diagnostic::error().with_message(msg);
+            term::emit(&mut w, &config, &src, &diag).unwrap();
+            writeln!(&mut w, "reading project failed").unwrap();
+            return false;
+          }
+          mlb = proj.mlb;
+        }
+      }
+      Err(e) => {
+        let diag = Diagnostic::error().with_message(e.message());
+        term::emit(&mut w, &config, &src, &diag).unwrap();
+        writeln!(&mut w, "reading project failed").unwrap();
+        return false;
+      }
+    }
+  }
+  let mut store = intern::StrStoreMut::new();
+  for name in names.iter() {
+    match std::fs::read_to_string(name) {
+      Ok(s) => src.insert(name.clone(), s),
       Err(e) => {
         let diag = Diagnostic::error().with_message(format!("{}: {}", name, e));
         term::emit(&mut w, &config, &src, &diag).unwrap();
@@ -58,40 +110,73 @@ fn run() -> bool {
   }
   let store = store.finish();
   let mut top_decs = Vec::with_capacity(src.len());
+  let mut ok = true;
   for ((id, file), lexer) in src.iter().zip(lexers) {
-    match parse::get(lexer) {
-      Ok(xs) => {
-        if args.just_ast {
-          writeln!(w, "{}: {:#?}", file.name(), xs).unwrap();
-        } else {
-          top_decs.push((id, xs));
-        }
-      }
-      Err(e) => {
-        let diag = simple(e.val.message(&store), id, e.loc);
-        term::emit(&mut w, &config, &src, &diag).unwrap();
-        writeln!(&mut w, "parsing failed").unwrap();
-        return false;
-      }
+    let (xs, errors) = parse::get(lexer);
+    for e in errors {
+      let diag = simple(e.val.message(&store), id, e.loc);
+      term::emit(&mut w, &config, &src, &diag).unwrap();
+      ok = false;
     }
+    if args.just_ast {
+      writeln!(w, "{}: {:#?}", file.name(), xs).unwrap();
+    } else {
+      top_decs.push((id, xs));
+    }
+  }
+  if !ok {
+    writeln!(&mut w, "parsing failed").unwrap();
+    return false;
   }
   if args.just_ast {
     return true;
   }
   let mut s = statics::Statics::new();
-  for (id, xs) in top_decs {
-    for x in xs {
-      match s.get(&x) {
-        Ok(()) => {}
-        Err(e) => {
-          let diag = simple(e.val.message(&store), id, e.loc);
+  let mut ok = true;
+  let deny_warnings = args.deny_warnings;
+  match mlb {
+    None => {
+      for (file, (id, xs)) in top_decs.into_iter().enumerate() {
+        for x in xs {
+          for e in s.get(file, &x) {
+            let diag = statics_diagnostic(&store, id, &e);
+            term::emit(&mut w, &config, &src, &diag).unwrap();
+            if deny_warnings || e.val.severity() == statics::Severity::Error {
+              ok = false;
+            }
+          }
+        }
+      }
+    }
+    Some(mlb) => {
+      let std_basis = s.basis();
+      let res = mlb.ck(&std_basis, &store, |path, bs| {
+        let idx = names
+          .iter()
+          .position(|x| Path::new(x) == path)
+          .expect("MLB files are in the project");
+        let (id, xs) = &top_decs[idx];
+        let (ret, errors) = s.get_in(bs, idx, xs);
+        for e in errors {
+          let diag = statics_diagnostic(&store, *id, &e);
           term::emit(&mut w, &config, &src, &diag).unwrap();
-          writeln!(&mut w, "typechecking failed").unwrap();
-          return false;
+          if deny_warnings || e.val.severity() == statics::Severity::Error {
+            ok = false;
+          }
         }
+        ret
+      });
+      if let Err(e) = res {
+        let diag = Diagnostic::error().with_message(e.message());
+        term::emit(&mut w, &config, &src, &diag).unwrap();
+        ok = false;
       }
     }
   }
+  if !ok {
+    writeln!(&mut w, "typechecking failed").unwrap();
+    return false;
+  }
   s.finish();
   if !args.quiet {
     writeln!(&mut w, "no errors").unwrap();
@@ -330,7 +330,9 @@ pub enum Spec<I> {
   Include(Box<Located<SigExp<I>>>),
   /// requires specs.len() != 1
   Seq(Vec<Located<Spec<I>>>),
-  Sharing(Box<Located<Spec<I>>>, Vec<Long<I>>),
+  /// requires longs.len() >= 2. the bool is true iff this was `sharing type`, false if it was
+  /// `sharing` (of structures).
+  Sharing(Box<Located<Spec<I>>>, Vec<Long<I>>, bool),
 }
 
 /// A value description.
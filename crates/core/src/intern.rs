@@ -30,7 +30,7 @@ use std::fmt;
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub struct StrRef(usize);
 
-const SPECIAL_STR_REF: usize = 41;
+const SPECIAL_STR_REF: usize = 42;
 
 impl fmt::Debug for StrRef {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -86,6 +86,7 @@ impl StrRef {
   pub const MATCH: Self = Self(38);
   pub const BIND: Self = Self(39);
   pub const ABS: Self = Self(40);
+  pub const FUNCTOR_ARG: Self = Self(41);
 }
 
 /// A mutable factory of StrRefs. Allows creating new StrRefs from Strings.
@@ -141,6 +142,8 @@ impl StrStoreMut {
       s("Match") => StrRef::MATCH,
       s("Bind") => StrRef::BIND,
       s("abs") => StrRef::ABS,
+      // not a valid identifier, so it cannot be written by the user.
+      s("<functor argument>") => StrRef::FUNCTOR_ARG,
     ];
     assert_eq!(store.len(), SPECIAL_STR_REF);
     Self {
@@ -188,4 +191,10 @@ impl StrStore {
       .expect("gave a StrStore a StrRef that didn't come from its StrStoreMut")
       .as_str()
   }
+
+  /// Returns the StrRef corresponding to this string slice, if there is one. This is slow, since
+  /// StrStores are optimized for going the other way.
+  pub fn lookup(&self, s: &str) -> Option<StrRef> {
+    self.store.iter().position(|x| x == s).map(StrRef)
+  }
 }
@@ -533,7 +533,7 @@ fn alpha_num(b: u8) -> Option<AlphaNum> {
 }
 
 fn is_formatting(b: u8) -> bool {
-  matches!(b, b' ' | b'\t' | b'\n' | 12)
+  matches!(b, b' ' | b'\t' | b'\n' | b'\r' | 12)
 }
 
 fn dec(b: u8) -> Option<u8> {
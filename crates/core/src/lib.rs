@@ -5,8 +5,10 @@
 pub mod ast;
 pub mod intern;
 pub mod lex;
+pub mod line_index;
 pub mod loc;
 pub mod parse;
+pub mod project;
 pub mod statics;
 pub mod token;
 
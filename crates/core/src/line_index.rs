@@ -0,0 +1,165 @@
+//! Conversion between byte indices and line/character positions.
+
+use std::ops::Range;
+
+/// A position in a file. Both the line and the character are 0-based. The character is in UTF-16
+/// code units, as in the Language Server Protocol.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Position {
+  /// The line.
+  pub line: usize,
+  /// The character in the line.
+  pub character: usize,
+}
+
+/// An index of the lines of a file, for converting between byte indices (as in `Loc`) and
+/// positions. Lines end with `\n`, `\r\n`, or `\r`.
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+  /// The byte index of the start of each line. The first is 0.
+  starts: Vec<usize>,
+  /// The byte index of the end of each line, not including the line ending.
+  ends: Vec<usize>,
+  /// The non-ASCII characters in the file, in order.
+  wide: Vec<Wide>,
+}
+
+/// A non-ASCII character, which has a different number of bytes than UTF-16 code units.
+#[derive(Debug, Clone)]
+struct Wide {
+  idx: usize,
+  len_utf8: usize,
+  len_utf16: usize,
+}
+
+impl LineIndex {
+  /// Returns a new LineIndex for the file with contents `text`.
+  pub fn new(text: &str) -> Self {
+    let mut starts = vec![0];
+    let mut ends = Vec::new();
+    let mut wide = Vec::new();
+    let mut iter = text.char_indices().peekable();
+    while let Some((idx, c)) = iter.next() {
+      match c {
+        '\n' => {
+          ends.push(idx);
+          starts.push(idx + 1);
+        }
+        '\r' => {
+          ends.push(idx);
+          if let Some(&(_, '\n')) = iter.peek() {
+            iter.next();
+            starts.push(idx + 2);
+          } else {
+            starts.push(idx + 1);
+          }
+        }
+        _ => {
+          if !c.is_ascii() {
+            wide.push(Wide {
+              idx,
+              len_utf8: c.len_utf8(),
+              len_utf16: c.len_utf16(),
+            });
+          }
+        }
+      }
+    }
+    ends.push(text.len());
+    Self { starts, ends, wide }
+  }
+
+  /// Returns the 0-based line containing the byte index `idx`. A line ending is on the line it
+  /// ends, and indices past the end of the file are on the last line.
+  pub fn line(&self, idx: usize) -> usize {
+    match self.starts.binary_search(&idx) {
+      Ok(line) => line,
+      Err(line) => line - 1,
+    }
+  }
+
+  /// Returns the byte indices of the 0-based line `line`, not including its line ending, or `None`
+  /// if there is no such line.
+  pub fn line_range(&self, line: usize) -> Option<Range<usize>> {
+    Some(*self.starts.get(line)?..*self.ends.get(line)?)
+  }
+
+  /// Returns the position of the byte index `idx`. Indices in a line ending are at the end of the
+  /// line, and indices past the end of the file are at the end of the last line.
+  pub fn position(&self, idx: usize) -> Position {
+    let line = self.line(idx);
+    let start = self.starts[line];
+    let idx = idx.min(self.ends[line]);
+    let character = self
+      .wide_in(start..idx)
+      .iter()
+      .fold(idx - start, |ac, w| ac - w.len_utf8 + w.len_utf16);
+    Position { line, character }
+  }
+
+  /// The inverse of `position`. Positions past the end of a line are at the end of that line, and
+  /// positions past the end of the file are at the end of the file.
+  pub fn byte_idx(&self, pos: Position) -> usize {
+    let range = match self.line_range(pos.line) {
+      Some(x) => x,
+      None => return *self.ends.last().unwrap(),
+    };
+    let mut idx = range.start;
+    let mut rest = pos.character;
+    for w in self.wide_in(range.clone()) {
+      let ascii = w.idx - idx;
+      if rest <= ascii {
+        return idx + rest;
+      }
+      rest -= ascii;
+      idx = w.idx;
+      if rest < w.len_utf16 {
+        return idx;
+      }
+      rest -= w.len_utf16;
+      idx += w.len_utf8;
+    }
+    (idx + rest).min(range.end)
+  }
+
+  /// Returns the non-ASCII characters that start in the range.
+  fn wide_in(&self, range: Range<usize>) -> &[Wide] {
+    let start = self.wide.partition_point(|w| w.idx < range.start);
+    let end = self.wide.partition_point(|w| w.idx < range.end);
+    &self.wide[start..end]
+  }
+}
+
+#[test]
+fn test_line_index() {
+  let pos = |line, character| Position { line, character };
+  let lines = LineIndex::new("ab\ncd\r\nef\rg");
+  assert_eq!(lines.line_range(0), Some(0..2));
+  assert_eq!(lines.line_range(1), Some(3..5));
+  assert_eq!(lines.line_range(2), Some(7..9));
+  assert_eq!(lines.line_range(3), Some(10..11));
+  assert_eq!(lines.line_range(4), None);
+  assert_eq!(lines.line(2), 0);
+  assert_eq!(lines.line(3), 1);
+  assert_eq!(lines.line(6), 1);
+  assert_eq!(lines.line(7), 2);
+  assert_eq!(lines.line(11), 3);
+  assert_eq!(lines.position(0), pos(0, 0));
+  assert_eq!(lines.position(4), pos(1, 1));
+  assert_eq!(lines.position(6), pos(1, 2));
+  assert_eq!(lines.position(10), pos(3, 0));
+  assert_eq!(lines.position(11), pos(3, 1));
+  assert_eq!(lines.byte_idx(pos(1, 1)), 4);
+  assert_eq!(lines.byte_idx(pos(1, 9)), 5);
+  assert_eq!(lines.byte_idx(pos(2, 0)), 7);
+  assert_eq!(lines.byte_idx(pos(9, 0)), 11);
+  // é is two bytes and one UTF-16 code unit, 𝔸 is four bytes and two UTF-16 code units.
+  let lines = LineIndex::new("(* é *)\n\"𝔸\" x");
+  assert_eq!(lines.position(6), pos(0, 5));
+  assert_eq!(lines.position(8), pos(0, 7));
+  assert_eq!(lines.position(16), pos(1, 5));
+  assert_eq!(lines.byte_idx(pos(0, 5)), 6);
+  assert_eq!(lines.byte_idx(pos(1, 3)), 14);
+  assert_eq!(lines.byte_idx(pos(1, 5)), 16);
+  assert_eq!(lines.byte_idx(pos(1, 6)), 17);
+}
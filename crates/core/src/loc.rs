@@ -26,6 +26,11 @@ impl Loc {
     }
   }
 
+  /// Returns whether the byte index `idx` is in this.
+  pub fn contains(self, idx: usize) -> bool {
+    self.start <= idx && idx < self.end
+  }
+
   /// Wraps a value in a Loc.
   pub fn wrap<T>(self, val: T) -> Located<T> {
     Located { val, loc: self }
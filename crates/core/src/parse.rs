@@ -17,21 +17,34 @@ use std::convert::TryInto as _;
 pub type Result<T> = std::result::Result<T, Located<Error>>;
 
 /// Parse the tokens in the Lexer into a sequence of top-level definitions.
-pub fn get(lexer: Lexer) -> Result<Vec<Located<TopDec<StrRef>>>> {
+///
+/// Parsing does not stop at the first error. Instead, the parser records the error, skips ahead to
+/// the start of the next declaration, and keeps going. So, this returns both the top-level
+/// definitions which parsed successfully (some of which may be partial) and every error encountered
+/// along the way. The errors are empty iff the whole input parsed successfully.
+pub fn get(lexer: Lexer) -> (Vec<Located<TopDec<StrRef>>>, Vec<Located<Error>>) {
   let mut ret = Vec::new();
   let last_loc = match lexer.last_loc() {
     Some(x) => x,
-    None => return Ok(ret),
+    None => return (ret, Vec::new()),
   };
   let mut p = Parser::new(lexer, last_loc);
   loop {
     if let Token::EOF = p.peek().val {
       break;
     }
-    ret.push(p.top_dec()?);
+    let start = p.i;
+    match p.top_dec() {
+      Ok(top_dec) => ret.push(top_dec),
+      Err(e) => {
+        let at = e.loc;
+        p.err(e);
+        p.recover(start, at);
+      }
+    }
   }
   ret.shrink_to_fit();
-  Ok(ret)
+  (ret, p.errors)
 }
 
 /// An error emitted when parsing.
@@ -70,6 +83,10 @@ struct Parser {
   i: usize,
   ops: HashMap<StrRef, OpInfo>,
   last_loc: Loc,
+  errors: Vec<Located<Error>>,
+  /// where the last recovery stopped, if it stopped at a token that closes an enclosing construct
+  /// (or at EOF).
+  stuck: Option<Loc>,
 }
 
 // NOTE the `maybe` family of functions return Result<Option<T>>. these functions return:
@@ -83,6 +100,8 @@ impl Parser {
     Self {
       lexer,
       last_loc,
+      errors: Vec::new(),
+      stuck: None,
       i: 0,
       ops: hashmap![
         StrRef::CONS => OpInfo::right(5),
@@ -142,6 +161,109 @@ impl Parser {
     Err(tok.loc.wrap(Error::ExpectedButFound(want, tok.val.desc())))
   }
 
+  /// records an error. if the previous error was at the same location, or this error is at the
+  /// closing token where the last recovery stopped, this one is dropped, since it is likely just a
+  /// consequence of recovering from the previous one.
+  fn err(&mut self, e: Located<Error>) {
+    if self.stuck == Some(e.loc) || self.errors.last().map(|last| last.loc) == Some(e.loc) {
+      return;
+    }
+    self.errors.push(e);
+  }
+
+  /// after an error, skips ahead to where we can start parsing again: the start of the next
+  /// declaration, after the next `;`, or the token which closes the enclosing construct (like `end`
+  /// or `in`). `start` is where we were when we began parsing the thing that errored, and `at` is
+  /// where the error was. we always skip at least one token from `start`, to avoid looping forever.
+  fn recover(&mut self, start: usize, at: Loc) {
+    // the token that caused the error may have already been consumed. if so, back up to it, since
+    // it may begin the next declaration.
+    if self.i > start + 1 && matches!(self.lexer.get(self.i - 1), Some(tok) if tok.loc == at) {
+      self.i -= 1;
+    }
+    if self.i == start {
+      self.skip();
+    }
+    self.stuck = None;
+    // how many constructs have not yet been closed. this starts with the blocks, like `sig` or
+    // `struct`, which were opened by the thing that errored, since declarations inside those are not
+    // the start of the next declaration out here. unclosed brackets, and `let`s whose `in` was
+    // reached, are not counted: those are more likely to be missing their closing token.
+    let mut opened = Vec::new();
+    for tok in (start..self.i).filter_map(|idx| self.lexer.get(idx)) {
+      match tok.val {
+        Token::Struct
+        | Token::Sig
+        | Token::Let
+        | Token::Local
+        | Token::Abstype
+        | Token::LRound
+        | Token::LSquare
+        | Token::LCurly => opened.push(tok.val),
+        Token::In => {
+          if let Some(Token::Let) = opened.last() {
+            opened.pop();
+          }
+        }
+        Token::End | Token::RRound | Token::RSquare | Token::RCurly => {
+          opened.pop();
+        }
+        _ => {}
+      }
+    }
+    let mut depth = opened
+      .into_iter()
+      .filter(|tok| !matches!(tok, Token::LRound | Token::LSquare | Token::LCurly))
+      .count();
+    loop {
+      match self.peek().val {
+        Token::EOF => {
+          self.stuck = Some(self.peek().loc);
+          break;
+        }
+        Token::Semicolon if depth == 0 => {
+          self.skip();
+          break;
+        }
+        Token::Val
+        | Token::Fun
+        | Token::Type
+        | Token::Eqtype
+        | Token::Datatype
+        | Token::Abstype
+        | Token::Exception
+        | Token::Local
+        | Token::Open
+        | Token::Infix
+        | Token::Infixr
+        | Token::Nonfix
+        | Token::Structure
+        | Token::Signature
+        | Token::Functor
+        | Token::Include
+          if depth == 0 =>
+        {
+          break
+        }
+        Token::End | Token::In | Token::RRound | Token::RSquare | Token::RCurly if depth == 0 => {
+          self.stuck = Some(self.peek().loc);
+          break;
+        }
+        Token::Struct
+        | Token::Sig
+        | Token::Let
+        | Token::Local
+        | Token::Abstype
+        | Token::LRound
+        | Token::LSquare
+        | Token::LCurly => depth += 1,
+        Token::End | Token::RRound | Token::RSquare | Token::RCurly => depth -= 1,
+        _ => {}
+      }
+      self.skip();
+    }
+  }
+
   fn top_dec(&mut self) -> Result<Located<TopDec<StrRef>>> {
     let tok = self.peek();
     let begin = tok.loc;
@@ -169,18 +291,44 @@ impl Parser {
         loop {
           let fun_id = self.alpha_num_id()?;
           self.eat(Token::LRound)?;
-          let str_id = self.alpha_num_id()?;
-          self.eat(Token::Colon)?;
-          let sig_exp = self.sig_exp()?;
-          self.eat(Token::RRound)?;
-          let str_exp = self.str_exp_sugar()?;
-          fun_binds.push(FunBind {
-            fun_id,
-            str_id,
-            sig_exp,
-            str_exp,
-          });
+          let is_str_id = matches!(self.peek().val, Token::Ident(_, IdentType::AlphaNum))
+            && matches!(self.lexer.get(self.i + 1), Some(tok) if tok.val == Token::Colon);
+          let fun_bind = if is_str_id {
+            let str_id = self.alpha_num_id()?;
+            self.eat(Token::Colon)?;
+            let sig_exp = self.sig_exp()?;
+            self.eat(Token::RRound)?;
+            let str_exp = self.str_exp_sugar()?;
+            FunBind {
+              fun_id,
+              str_id,
+              sig_exp,
+              str_exp,
+            }
+          } else {
+            // `functor F (spec) = str_exp` is sugar for `functor F (X : sig spec end) = let open X
+            // in str_exp end`, where X is a name the user cannot write.
+            let spec = self.spec()?;
+            self.eat(Token::RRound)?;
+            let str_exp = self.str_exp_sugar()?;
+            let str_id = spec.loc.wrap(StrRef::FUNCTOR_ARG);
+            let long = Long {
+              structures: Vec::new(),
+              last: str_id,
+            };
+            let open = str_exp
+              .loc
+              .wrap(StrDec::Dec(str_exp.loc.wrap(Dec::Open(vec![long]))));
+            FunBind {
+              fun_id,
+              str_id,
+              sig_exp: spec.loc.wrap(SigExp::Sig(spec)),
+              str_exp: str_exp.loc.wrap(StrExp::Let(open, str_exp.into())),
+            }
+          };
+          fun_binds.push(fun_bind);
           if let Token::And = self.peek().val {
+            self.skip();
             continue;
           } else {
             break;
@@ -248,9 +396,13 @@ impl Parser {
         self.skip();
         if let Token::LRound = self.peek().val {
           self.skip();
-          let exp = match self.maybe_str_dec()? {
-            Some(sd) => sd.loc.wrap(StrExp::Struct(sd)),
-            None => self.str_exp()?,
+          // `F (str_dec)` is sugar for `F (struct str_dec end)`.
+          let str_dec = self.str_dec()?;
+          let exp = match &str_dec.val {
+            StrDec::Seq(str_decs) if str_decs.is_empty() && self.peek().val != Token::RRound => {
+              self.str_exp()?
+            }
+            _ => str_dec.loc.wrap(StrExp::Struct(str_dec)),
           };
           self.eat(Token::RRound)?;
           StrExp::FunctorApp(begin.wrap(id), exp.into())
@@ -340,8 +492,20 @@ impl Parser {
       Token::Ident(id, IdentType::AlphaNum) => SigExp::SigId(begin.wrap(id)),
       _ => return self.fail("a signature expression", tok),
     };
-    while let Token::Where = self.peek().val {
+    let mut after_where = false;
+    loop {
+      match self.peek().val {
+        Token::Where => {}
+        // `where type ... and type ...` is sugar for `where type ... where type ...`. We check for
+        // the `type` since an `and` without it may begin another binding.
+        Token::And
+          if after_where
+            && matches!(self.lexer.get(self.i + 1), Some(tok) if tok.val == Token::Type) => {}
+        _ => break,
+      }
       self.skip();
+      self.eat(Token::Type)?;
+      after_where = true;
       let ty_vars = self.ty_var_seq()?;
       let ty_con = self.long_id(true)?;
       self.eat(Token::Equal)?;
@@ -354,7 +518,7 @@ impl Parser {
   fn maybe_spec(&mut self) -> Result<Option<Located<Spec<StrRef>>>> {
     let tok = self.peek();
     let begin = tok.loc;
-    let mut ret = match tok.val {
+    let ret = match tok.val {
       Token::Val => {
         self.skip();
         let mut val_descs = Vec::new();
@@ -440,24 +604,6 @@ impl Parser {
       }
       _ => return Ok(None),
     };
-    while let Token::Sharing = self.peek().val {
-      self.skip();
-      self.eat(Token::Type)?;
-      let mut ty_cons = Vec::new();
-      loop {
-        ty_cons.push(self.long_id(true)?);
-        if let Token::Equal = self.peek().val {
-          self.skip();
-        } else {
-          break;
-        }
-      }
-      if ty_cons.len() < 2 {
-        return self.fail("an identifier", self.peek());
-      }
-      ty_cons.shrink_to_fit();
-      ret = Spec::Sharing(self.wrap(begin, ret).into(), ty_cons);
-    }
     Ok(Some(self.wrap(begin, ret)))
   }
 
@@ -478,7 +624,54 @@ impl Parser {
   }
 
   fn spec(&mut self) -> Result<Located<Spec<StrRef>>> {
-    self.semicolon_seq(Self::maybe_spec, Spec::Seq)
+    let mut ret = self.semicolon_seq(Self::maybe_spec, Spec::Seq)?;
+    // `sharing` applies to all the specs before it in the signature, not just the last one.
+    while let Token::Sharing = self.peek().val {
+      self.skip();
+      let is_ty = if let Token::Type = self.peek().val {
+        self.skip();
+        true
+      } else {
+        false
+      };
+      let mut longs = Vec::new();
+      loop {
+        let long = if is_ty {
+          self.long_id(true)?
+        } else {
+          self.long_alpha_num_id()?
+        };
+        longs.push(long);
+        if let Token::Equal = self.peek().val {
+          self.skip();
+        } else {
+          break;
+        }
+      }
+      if longs.len() < 2 {
+        return self.fail("an identifier", self.peek());
+      }
+      longs.shrink_to_fit();
+      let loc = ret.loc.span(longs.last().unwrap().loc());
+      let sharing = loc.wrap(Spec::Sharing(ret.into(), longs, is_ty));
+      if let Token::Semicolon = self.peek().val {
+        self.skip();
+      }
+      let rest = self.semicolon_seq(Self::maybe_spec, Spec::Seq)?;
+      ret = match rest.val {
+        Spec::Seq(specs) if specs.is_empty() => sharing,
+        Spec::Seq(mut specs) => {
+          let loc = sharing.loc.span(rest.loc);
+          specs.insert(0, sharing);
+          loc.wrap(Spec::Seq(specs))
+        }
+        _ => {
+          let loc = sharing.loc.span(rest.loc);
+          loc.wrap(Spec::Seq(vec![sharing, rest]))
+        }
+      };
+    }
+    Ok(ret)
   }
 
   fn maybe_at_exp(&mut self) -> Result<Option<Located<Exp<StrRef>>>> {
@@ -1579,10 +1772,21 @@ impl Parser {
     G: FnOnce(Vec<Located<T>>) -> T,
   {
     let mut xs = Vec::new();
-    while let Some(x) = one(self)? {
-      xs.push(x);
-      if let Token::Semicolon = self.peek().val {
-        self.skip();
+    loop {
+      let start = self.i;
+      match one(self) {
+        Ok(Some(x)) => {
+          xs.push(x);
+          if let Token::Semicolon = self.peek().val {
+            self.skip();
+          }
+        }
+        Ok(None) => break,
+        Err(e) => {
+          let at = e.loc;
+          self.err(e);
+          self.recover(start, at);
+        }
       }
     }
     xs.shrink_to_fit();
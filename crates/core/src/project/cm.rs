@@ -0,0 +1,190 @@
+//! SML/NJ Compilation Manager (CM) files, like `sources.cm`:
+//!
+//! ```text
+//! Group is
+//!   $/basis.cm
+//!   util.sml
+//!   lib/sources.cm
+//!   main.sml
+//! ```
+//!
+//! Both `Group` and `Library` files are supported. The export list of a `Library` is ignored, since
+//! everything is checked together. Members which are themselves CM files are expanded in place,
+//! each at most once. Members with an anchored path, like `$/basis.cm`, name libraries outside the
+//! project. These are skipped, since we only know about the standard basis, which is always
+//! available.
+//!
+//! A member may have an explicit class, like `parse.grm.sml : sml`. Otherwise, its class comes from
+//! its extension, as in CM.
+//!
+//! Note that CM itself works out the order in which to compile the members from the dependencies
+//! between them. We don't do that, and instead use the order in which they're listed.
+
+use crate::project::{join, Error};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// The conventional name of the CM file at the root of a project.
+pub const FILE_NAME: &str = "sources.cm";
+
+/// Appends to `ret` the SML source files of the CM file at `path`, and of every CM file it
+/// (transitively) references, other than those in `seen`. `read` is used to read the CM files.
+/// Members we don't know how to check, like ML-Yacc grammars, are skipped, with a warning appended
+/// to `warnings`.
+pub fn get<F>(
+  path: &Path,
+  read: &mut F,
+  seen: &mut HashSet<PathBuf>,
+  ret: &mut Vec<PathBuf>,
+  warnings: &mut Vec<Error>,
+) -> Result<(), Error>
+where
+  F: FnMut(&Path) -> std::io::Result<String>,
+{
+  if !seen.insert(path.to_owned()) {
+    return Ok(());
+  }
+  let contents = read(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+  let members = members(&contents).map_err(|msg| Error::InvalidCm(path.to_owned(), msg))?;
+  let dir = path.parent().unwrap_or_else(|| Path::new(""));
+  for member in members {
+    if member.path.starts_with('$') {
+      continue;
+    }
+    let class = member.class.map(str::to_ascii_lowercase);
+    let member = join(dir, Path::new(member.path));
+    let class = match class {
+      Some(x) => Some(x),
+      None => member
+        .extension()
+        .and_then(|x| x.to_str())
+        .and_then(|x| match x {
+          "cm" => Some("cm".to_owned()),
+          "sml" | "sig" | "fun" | "ML" => Some("sml".to_owned()),
+          _ => None,
+        }),
+    };
+    match class.as_deref() {
+      Some("cm") => get(&member, read, seen, ret, warnings)?,
+      Some("sml") => {
+        if seen.insert(member.clone()) {
+          ret.push(member);
+        }
+      }
+      _ => warnings.push(Error::UnsupportedCmMember(path.to_owned(), member)),
+    }
+  }
+  Ok(())
+}
+
+/// A member of a CM file.
+#[derive(Debug, PartialEq, Eq)]
+struct Member<'a> {
+  /// The path, as written.
+  path: &'a str,
+  /// The explicit class, like `sml` in `foo.sml : sml`, if there is one.
+  class: Option<&'a str>,
+}
+
+/// Returns the members listed in the CM file with the given contents.
+fn members(contents: &str) -> Result<Vec<Member<'_>>, &'static str> {
+  let mut words = words(contents)?.into_iter();
+  match words.next() {
+    Some("Group") | Some("Library") => {}
+    _ => return Err("expected `Group` or `Library`"),
+  }
+  // skip the export list.
+  if !words.any(|w| w == "is") {
+    return Err("expected `is`");
+  }
+  let mut ret = Vec::new();
+  while let Some(w) = words.next() {
+    if w.starts_with('#') {
+      return Err("conditional compilation is not supported");
+    }
+    // an explicit member class, like `foo.sml : sml`.
+    if w == ":" {
+      let class = words.next().ok_or("expected a member class after `:`")?;
+      match ret.last_mut() {
+        Some(Member {
+          class: x @ None, ..
+        }) => *x = Some(class),
+        _ => return Err("expected a member before `:`"),
+      }
+      continue;
+    }
+    ret.push(Member {
+      path: w,
+      class: None,
+    });
+  }
+  Ok(ret)
+}
+
+/// Splits the contents into whitespace-separated words, ignoring comments.
+fn words(contents: &str) -> Result<Vec<&str>, &'static str> {
+  let bs = contents.as_bytes();
+  let mut ret = Vec::new();
+  let mut i = 0;
+  while i < bs.len() {
+    if bs[i].is_ascii_whitespace() {
+      i += 1;
+    } else if bs[i..].starts_with(b"(*") {
+      // comments nest, as in SML.
+      let mut depth = 0usize;
+      loop {
+        if i >= bs.len() {
+          return Err("unmatched open comment");
+        } else if bs[i..].starts_with(b"(*") {
+          depth += 1;
+          i += 2;
+        } else if bs[i..].starts_with(b"*)") {
+          depth -= 1;
+          i += 2;
+          if depth == 0 {
+            break;
+          }
+        } else {
+          i += 1;
+        }
+      }
+    } else {
+      let start = i;
+      while i < bs.len() && !bs[i].is_ascii_whitespace() && !bs[i..].starts_with(b"(*") {
+        i += 1;
+      }
+      ret.push(&contents[start..i]);
+    }
+  }
+  Ok(ret)
+}
+
+#[test]
+fn test_members() {
+  let contents = r#"
+(* the library (* nested *) *)
+Library
+  structure Foo
+  signature FOO
+is
+  $/basis.cm
+  foo.sig
+  foo.sml : sml
+  parse.grm.sml : SML
+  lib/sources.cm
+"#;
+  let member = |path, class| Member { path, class };
+  assert_eq!(
+    members(contents).unwrap(),
+    vec![
+      member("$/basis.cm", None),
+      member("foo.sig", None),
+      member("foo.sml", Some("sml")),
+      member("parse.grm.sml", Some("SML")),
+      member("lib/sources.cm", None),
+    ]
+  );
+  assert!(members("Group is : sml").is_err());
+  assert!(members("Group foo.sml").is_err());
+  assert!(members("Group is (* foo.sml").is_err());
+}
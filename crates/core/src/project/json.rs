@@ -0,0 +1,47 @@
+//! The `millet.json` project manifest. It is a JSON object whose `files` key is an array of the
+//! paths to the SML source files in the project, in the order they should be checked:
+//!
+//! ```json
+//! { "files": ["util.sml", "main.sml"] }
+//! ```
+
+use crate::project::Error;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// The name of the manifest file, which is expected to be at the root of a workspace.
+pub const FILE_NAME: &str = "millet.json";
+
+/// Returns the paths listed in the manifest at `path`, which has the given contents. The paths are
+/// as written in the manifest.
+pub fn get(path: &Path, contents: &str) -> Result<Vec<PathBuf>, Error> {
+  let val: Value =
+    serde_json::from_str(contents).map_err(|e| Error::InvalidJson(path.to_owned(), e))?;
+  let invalid = |msg| Error::InvalidManifest(path.to_owned(), msg);
+  let files = val
+    .as_object()
+    .ok_or_else(|| invalid("expected an object"))?
+    .get("files")
+    .ok_or_else(|| invalid("missing `files`"))?
+    .as_array()
+    .ok_or_else(|| invalid("expected `files` to be an array"))?;
+  files
+    .iter()
+    .map(|x| match x.as_str() {
+      Some(x) => Ok(PathBuf::from(x)),
+      None => Err(invalid("expected every element of `files` to be a string")),
+    })
+    .collect()
+}
+
+#[test]
+fn test_get() {
+  let path = Path::new(FILE_NAME);
+  let files = get(path, r#"{ "files": ["a.sml", "dir/b.sml"] }"#).unwrap();
+  assert_eq!(
+    files,
+    vec![PathBuf::from("a.sml"), PathBuf::from("dir/b.sml")]
+  );
+  assert!(get(path, r#"{ "files": "a.sml" }"#).is_err());
+  assert!(get(path, r#"["a.sml"]"#).is_err());
+}
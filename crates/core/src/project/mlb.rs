@@ -0,0 +1,601 @@
+//! ML Basis (MLB) files, as used by MLton and Poly/ML:
+//!
+//! ```text
+//! $(SML_LIB)/basis/basis.mlb
+//! local
+//!   util.sml
+//! in
+//!   structure Stack
+//!   stack.sml
+//! end
+//! main.sml
+//! ```
+//!
+//! Unlike the other project files, MLB files control the scope of the declarations in the files, so
+//! checking such a project is more involved than checking the files one after another. All the
+//! basis declarations and expressions are supported. Annotations are accepted, but ignored. Paths
+//! which use a path variable, like `$(SML_LIB)/basis/basis.mlb`, name libraries outside the
+//! project. These are skipped, since we only know about the standard basis, which is always
+//! available.
+
+use crate::intern::StrStore;
+use crate::project::{join, Error};
+use crate::statics::{Basis, Namespace};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// A basis declaration.
+#[derive(Debug, Clone)]
+#[allow(missing_docs)]
+pub enum BasDec {
+  Seq(Vec<BasDec>),
+  Basis(Vec<(String, BasExp)>),
+  Open(Vec<String>),
+  Local(Box<BasDec>, Box<BasDec>),
+  /// Bindings of new names to old names, like `structure A = B`.
+  Bind(Namespace, Vec<(String, String)>),
+  Sml(PathBuf),
+  Mlb(PathBuf),
+}
+
+/// A basis expression.
+#[derive(Debug, Clone)]
+#[allow(missing_docs)]
+pub enum BasExp {
+  Bas(BasDec),
+  Name(String),
+  Let(BasDec, Box<BasExp>),
+}
+
+/// The MLB files in a project.
+#[derive(Debug, Clone)]
+pub struct Mlb {
+  root: PathBuf,
+  files: HashMap<PathBuf, BasDec>,
+}
+
+/// Returns the MLB files of the project whose root MLB file is at `path`, and appends to `ret` the
+/// SML source files in the project, in the order they should be checked. Each file is included at
+/// most once. `read` is used to read the MLB files.
+pub fn get<F>(path: &Path, read: &mut F, ret: &mut Vec<PathBuf>) -> Result<Mlb, Error>
+where
+  F: FnMut(&Path) -> std::io::Result<String>,
+{
+  let mut files = HashMap::new();
+  load(path, read, &mut HashSet::new(), &mut files, ret)?;
+  Ok(Mlb {
+    root: path.to_owned(),
+    files,
+  })
+}
+
+fn load<F>(
+  path: &Path,
+  read: &mut F,
+  loading: &mut HashSet<PathBuf>,
+  files: &mut HashMap<PathBuf, BasDec>,
+  ret: &mut Vec<PathBuf>,
+) -> Result<(), Error>
+where
+  F: FnMut(&Path) -> std::io::Result<String>,
+{
+  if files.contains_key(path) {
+    return Ok(());
+  }
+  if !loading.insert(path.to_owned()) {
+    return Err(invalid(path, "the MLB file includes itself"));
+  }
+  let contents = read(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+  let dir = path.parent().unwrap_or_else(|| Path::new(""));
+  let mut p = Parser {
+    toks: toks(&contents).map_err(|msg| invalid(path, msg))?,
+    i: 0,
+    dir,
+  };
+  let dec = p.bas_dec().map_err(|msg| invalid(path, msg))?;
+  if let Some(tok) = p.toks.get(p.i) {
+    return Err(invalid(path, format!("unexpected `{}`", tok)));
+  }
+  let mut paths = Vec::new();
+  paths_in_dec(&dec, &mut paths);
+  for p in paths {
+    match p {
+      BasDec::Sml(p) => {
+        if !ret.contains(p) {
+          ret.push(p.clone());
+        }
+      }
+      BasDec::Mlb(p) => load(p, read, loading, files, ret)?,
+      _ => unreachable!("paths_in_dec only returns paths"),
+    }
+  }
+  loading.remove(path);
+  files.insert(path.to_owned(), dec);
+  Ok(())
+}
+
+/// Appends to `ret` the `Sml` and `Mlb` paths in the declaration, in order.
+fn paths_in_dec<'a>(dec: &'a BasDec, ret: &mut Vec<&'a BasDec>) {
+  match dec {
+    BasDec::Seq(decs) => {
+      for dec in decs {
+        paths_in_dec(dec, ret);
+      }
+    }
+    BasDec::Basis(binds) => {
+      for (_, exp) in binds {
+        paths_in_exp(exp, ret);
+      }
+    }
+    BasDec::Open(_) | BasDec::Bind(_, _) => {}
+    BasDec::Local(fst, snd) => {
+      paths_in_dec(fst, ret);
+      paths_in_dec(snd, ret);
+    }
+    BasDec::Sml(_) | BasDec::Mlb(_) => ret.push(dec),
+  }
+}
+
+fn paths_in_exp<'a>(exp: &'a BasExp, ret: &mut Vec<&'a BasDec>) {
+  match exp {
+    BasExp::Bas(dec) => paths_in_dec(dec, ret),
+    BasExp::Name(_) => {}
+    BasExp::Let(dec, exp) => {
+      paths_in_dec(dec, ret);
+      paths_in_exp(exp, ret);
+    }
+  }
+}
+
+fn invalid<M>(path: &Path, msg: M) -> Error
+where
+  M: Into<String>,
+{
+  Error::InvalidMlb(path.to_owned(), msg.into())
+}
+
+impl Mlb {
+  /// Checks the project. `ck_file` is called to check each SML file in a basis, and should return a
+  /// basis containing only what the file declares. It is called at most once for each file, so that
+  /// errors in a file mentioned more than once are reported once. Later mentions of the file reuse
+  /// what it declared the first time. MLB files are evaluated in `std`, which should contain the
+  /// standard library. `store` is used to look up names.
+  pub fn ck<F>(&self, std: &Basis, store: &StrStore, ck_file: F) -> Result<(), Error>
+  where
+    F: FnMut(&Path, &Basis) -> Basis,
+  {
+    let mut ev = Evaluator {
+      mlb: self,
+      std,
+      store,
+      ck_file,
+      done: HashMap::new(),
+      checked: HashMap::new(),
+    };
+    ev.mlb(&self.root)?;
+    Ok(())
+  }
+}
+
+/// What a basis declaration or expression evaluates to.
+#[derive(Clone, Default)]
+struct Env {
+  bases: HashMap<String, Env>,
+  bs: Basis,
+}
+
+impl Env {
+  fn extend(&mut self, other: Self) {
+    self.bases.extend(other.bases);
+    self.bs.extend(other.bs);
+  }
+}
+
+struct Evaluator<'a, F> {
+  mlb: &'a Mlb,
+  std: &'a Basis,
+  store: &'a StrStore,
+  ck_file: F,
+  /// the MLB files which have been evaluated. each is evaluated at most once.
+  done: HashMap<&'a Path, Env>,
+  /// what each SML file which has been checked declared. each is checked at most once.
+  checked: HashMap<&'a Path, Basis>,
+}
+
+impl<'a, F> Evaluator<'a, F>
+where
+  F: FnMut(&Path, &Basis) -> Basis,
+{
+  fn mlb(&mut self, path: &'a Path) -> Result<Env, Error> {
+    if let Some(env) = self.done.get(path) {
+      return Ok(env.clone());
+    }
+    // MLB files are evaluated in the initial environment, not the one in which they're mentioned.
+    let cx = Env {
+      bases: HashMap::new(),
+      bs: self.std.clone(),
+    };
+    let ret = self.dec(path, &cx, &self.mlb.files[path])?;
+    self.done.insert(path, ret.clone());
+    Ok(ret)
+  }
+
+  fn dec(&mut self, path: &'a Path, cx: &Env, dec: &'a BasDec) -> Result<Env, Error> {
+    let mut ret = Env::default();
+    match dec {
+      BasDec::Seq(decs) => {
+        let mut cx = cx.clone();
+        for dec in decs {
+          let env = self.dec(path, &cx, dec)?;
+          cx.extend(env.clone());
+          ret.extend(env);
+        }
+      }
+      BasDec::Basis(binds) => {
+        for (name, exp) in binds {
+          let env = self.exp(path, cx, exp)?;
+          ret.bases.insert(name.clone(), env);
+        }
+      }
+      BasDec::Open(names) => {
+        for name in names {
+          match cx.bases.get(name) {
+            Some(env) => ret.extend(env.clone()),
+            None => return Err(invalid(path, format!("undefined basis: {}", name))),
+          }
+        }
+      }
+      BasDec::Local(fst, snd) => {
+        let env = self.dec(path, cx, fst)?;
+        let mut cx = cx.clone();
+        cx.extend(env);
+        ret = self.dec(path, &cx, snd)?;
+      }
+      BasDec::Bind(ns, binds) => {
+        for (new, old) in binds {
+          let undefined = || invalid(path, format!("undefined {}: {}", ns_desc(*ns), old));
+          let old = self.store.lookup(old).ok_or_else(undefined)?;
+          // if the new name was never interned, it's not mentioned anywhere else in the project, so
+          // there's no point binding it. but we still check the old name is bound.
+          let bs = match self.store.lookup(new) {
+            Some(new) => cx.bs.rebind(*ns, old, new),
+            None => cx.bs.rebind(*ns, old, old).map(|_| Basis::default()),
+          };
+          ret.bs.extend(bs.ok_or_else(undefined)?);
+        }
+      }
+      BasDec::Sml(file) => {
+        ret.bs = match self.checked.get(file.as_path()) {
+          Some(bs) => bs.clone(),
+          None => {
+            let bs = (self.ck_file)(file, &cx.bs);
+            self.checked.insert(file, bs.clone());
+            bs
+          }
+        }
+      }
+      BasDec::Mlb(file) => ret = self.mlb(file)?,
+    }
+    Ok(ret)
+  }
+
+  fn exp(&mut self, path: &'a Path, cx: &Env, exp: &'a BasExp) -> Result<Env, Error> {
+    match exp {
+      BasExp::Bas(dec) => self.dec(path, cx, dec),
+      BasExp::Name(name) => match cx.bases.get(name) {
+        Some(env) => Ok(env.clone()),
+        None => Err(invalid(path, format!("undefined basis: {}", name))),
+      },
+      BasExp::Let(dec, exp) => {
+        let env = self.dec(path, cx, dec)?;
+        let mut cx = cx.clone();
+        cx.extend(env);
+        self.exp(path, &cx, exp)
+      }
+    }
+  }
+}
+
+fn ns_desc(ns: Namespace) -> &'static str {
+  match ns {
+    Namespace::Structure => "structure",
+    Namespace::Signature => "signature",
+    Namespace::Functor => "functor",
+  }
+}
+
+struct Parser<'a> {
+  toks: Vec<&'a str>,
+  i: usize,
+  dir: &'a Path,
+}
+
+type ParseResult<T> = Result<T, String>;
+
+impl<'a> Parser<'a> {
+  fn peek(&self) -> Option<&'a str> {
+    self.toks.get(self.i).copied()
+  }
+
+  fn eat(&mut self, tok: &str) -> ParseResult<()> {
+    match self.peek() {
+      Some(t) if t == tok => {
+        self.i += 1;
+        Ok(())
+      }
+      Some(t) => Err(format!("expected `{}`, found `{}`", tok, t)),
+      None => Err(format!("expected `{}`, found end of file", tok)),
+    }
+  }
+
+  fn name(&mut self) -> ParseResult<String> {
+    match self.peek() {
+      Some(t) if is_name(t) => {
+        self.i += 1;
+        Ok(t.to_owned())
+      }
+      Some(t) => Err(format!("expected a name, found `{}`", t)),
+      None => Err("expected a name, found end of file".to_owned()),
+    }
+  }
+
+  /// parses a sequence of basis declarations, up until a token which cannot start one.
+  fn bas_dec(&mut self) -> ParseResult<BasDec> {
+    let mut ret = Vec::new();
+    while let Some(tok) = self.peek() {
+      let dec = match tok {
+        ";" => {
+          self.i += 1;
+          continue;
+        }
+        "basis" => {
+          self.i += 1;
+          let mut binds = Vec::new();
+          loop {
+            let name = self.name()?;
+            self.eat("=")?;
+            binds.push((name, self.bas_exp()?));
+            if self.peek() != Some("and") {
+              break;
+            }
+            self.i += 1;
+          }
+          BasDec::Basis(binds)
+        }
+        "open" => {
+          self.i += 1;
+          let mut names = vec![self.name()?];
+          while let Some(t) = self.peek() {
+            if !is_name(t) {
+              break;
+            }
+            self.i += 1;
+            names.push(t.to_owned());
+          }
+          BasDec::Open(names)
+        }
+        "local" => {
+          self.i += 1;
+          let fst = self.bas_dec()?;
+          self.eat("in")?;
+          let snd = self.bas_dec()?;
+          self.eat("end")?;
+          BasDec::Local(fst.into(), snd.into())
+        }
+        "structure" => self.binds(Namespace::Structure)?,
+        "signature" => self.binds(Namespace::Signature)?,
+        "functor" => self.binds(Namespace::Functor)?,
+        "ann" => {
+          self.i += 1;
+          if !matches!(self.peek(), Some(t) if is_string(t)) {
+            return Err("expected an annotation string".to_owned());
+          }
+          while matches!(self.peek(), Some(t) if is_string(t)) {
+            self.i += 1;
+          }
+          self.eat("in")?;
+          let dec = self.bas_dec()?;
+          self.eat("end")?;
+          dec
+        }
+        _ => match self.path(tok)? {
+          Some(dec) => {
+            self.i += 1;
+            dec
+          }
+          None => break,
+        },
+      };
+      ret.push(dec);
+    }
+    Ok(BasDec::Seq(ret))
+  }
+
+  /// parses bindings like `structure A = B and C`, where `C` is short for `C = C`.
+  fn binds(&mut self, ns: Namespace) -> ParseResult<BasDec> {
+    self.i += 1;
+    let mut binds = Vec::new();
+    loop {
+      let new = self.name()?;
+      let old = if self.peek() == Some("=") {
+        self.i += 1;
+        self.name()?
+      } else {
+        new.clone()
+      };
+      binds.push((new, old));
+      if self.peek() != Some("and") {
+        break;
+      }
+      self.i += 1;
+    }
+    Ok(BasDec::Bind(ns, binds))
+  }
+
+  fn bas_exp(&mut self) -> ParseResult<BasExp> {
+    match self.peek() {
+      Some("bas") => {
+        self.i += 1;
+        let dec = self.bas_dec()?;
+        self.eat("end")?;
+        Ok(BasExp::Bas(dec))
+      }
+      Some("let") => {
+        self.i += 1;
+        let dec = self.bas_dec()?;
+        self.eat("in")?;
+        let exp = self.bas_exp()?;
+        self.eat("end")?;
+        Ok(BasExp::Let(dec, exp.into()))
+      }
+      _ => Ok(BasExp::Name(self.name()?)),
+    }
+  }
+
+  /// returns the declaration for the path `tok`, or `None` if `tok` is not a path.
+  fn path(&self, tok: &str) -> ParseResult<Option<BasDec>> {
+    let tok = if is_string(tok) {
+      &tok[1..tok.len() - 1]
+    } else {
+      tok
+    };
+    let path = Path::new(tok);
+    let ext = match path.extension().and_then(|x| x.to_str()) {
+      Some(x) => x,
+      None => return Ok(None),
+    };
+    if tok.contains("$(") {
+      return Ok(Some(BasDec::Seq(Vec::new())));
+    }
+    let path = join(self.dir, path);
+    match ext {
+      "sml" | "sig" | "fun" | "ML" => Ok(Some(BasDec::Sml(path))),
+      "mlb" => Ok(Some(BasDec::Mlb(path))),
+      _ => Err(format!(
+        "unsupported path, expected an SML or MLB file: {}",
+        tok
+      )),
+    }
+  }
+}
+
+const KEYWORDS: [&str; 12] = [
+  "and",
+  "ann",
+  "bas",
+  "basis",
+  "end",
+  "functor",
+  "in",
+  "let",
+  "local",
+  "open",
+  "signature",
+  "structure",
+];
+
+fn is_name(tok: &str) -> bool {
+  !KEYWORDS.contains(&tok)
+    && tok.starts_with(|c: char| c.is_ascii_alphabetic())
+    && tok
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '\'')
+}
+
+fn is_string(tok: &str) -> bool {
+  tok.len() >= 2 && tok.starts_with('"') && tok.ends_with('"')
+}
+
+/// Splits the contents into tokens, ignoring comments. The tokens are `=`, `;`, string literals, and
+/// runs of other non-whitespace characters.
+fn toks(contents: &str) -> Result<Vec<&str>, &'static str> {
+  let bs = contents.as_bytes();
+  let mut ret = Vec::new();
+  let mut i = 0;
+  while i < bs.len() {
+    if bs[i].is_ascii_whitespace() {
+      i += 1;
+    } else if bs[i..].starts_with(b"(*") {
+      // comments nest, as in SML.
+      let mut depth = 0usize;
+      loop {
+        if i >= bs.len() {
+          return Err("unmatched open comment");
+        } else if bs[i..].starts_with(b"(*") {
+          depth += 1;
+          i += 2;
+        } else if bs[i..].starts_with(b"*)") {
+          depth -= 1;
+          i += 2;
+          if depth == 0 {
+            break;
+          }
+        } else {
+          i += 1;
+        }
+      }
+    } else if bs[i] == b'=' || bs[i] == b';' {
+      ret.push(&contents[i..i + 1]);
+      i += 1;
+    } else if bs[i] == b'"' {
+      let start = i;
+      i += 1;
+      while i < bs.len() && bs[i] != b'"' {
+        i += 1;
+      }
+      if i >= bs.len() {
+        return Err("unclosed string");
+      }
+      i += 1;
+      ret.push(&contents[start..i]);
+    } else {
+      let start = i;
+      while i < bs.len()
+        && !bs[i].is_ascii_whitespace()
+        && !matches!(bs[i], b'=' | b';' | b'"')
+        && !bs[i..].starts_with(b"(*")
+      {
+        i += 1;
+      }
+      ret.push(&contents[start..i]);
+    }
+  }
+  Ok(ret)
+}
+
+#[test]
+fn test_parse() {
+  let contents = r#"
+(* a library *)
+$(SML_LIB)/basis/basis.mlb
+basis Lib = bas lib.sml end
+local
+  open Lib
+  ann "milletDiagnosticsIgnore true" in util.sml end
+in
+  structure Foo = Bar and Quz;
+  main.sml
+end
+"#;
+  let mut p = Parser {
+    toks: toks(contents).unwrap(),
+    i: 0,
+    dir: Path::new("dir"),
+  };
+  let dec = p.bas_dec().unwrap();
+  assert_eq!(p.i, p.toks.len());
+  let mut paths = Vec::new();
+  paths_in_dec(&dec, &mut paths);
+  let paths: Vec<_> = paths
+    .into_iter()
+    .map(|x| match x {
+      BasDec::Sml(p) => p.clone(),
+      _ => panic!("not an SML path"),
+    })
+    .collect();
+  let want: Vec<_> = ["dir/lib.sml", "dir/util.sml", "dir/main.sml"]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+  assert_eq!(paths, want);
+}
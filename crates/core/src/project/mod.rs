@@ -0,0 +1,158 @@
+//! Projects, which are made of many source files, checked in a particular order.
+
+pub mod cm;
+pub mod json;
+pub mod mlb;
+
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
+/// An error when reading a project file.
+#[derive(Debug)]
+#[allow(missing_docs)]
+pub enum Error {
+  Io(PathBuf, std::io::Error),
+  InvalidJson(PathBuf, serde_json::Error),
+  InvalidManifest(PathBuf, &'static str),
+  InvalidCm(PathBuf, &'static str),
+  UnsupportedCmMember(PathBuf, PathBuf),
+  InvalidMlb(PathBuf, String),
+}
+
+impl Error {
+  /// A human-readable message describing this error.
+  pub fn message(&self) -> String {
+    match self {
+      Self::Io(path, e) => format!("{}: {}", path.display(), e),
+      Self::InvalidJson(path, e) => format!("{}: invalid JSON: {}", path.display(), e),
+      Self::InvalidManifest(path, msg) => format!("{}: invalid manifest: {}", path.display(), msg),
+      Self::InvalidCm(path, msg) => format!("{}: invalid CM file: {}", path.display(), msg),
+      Self::UnsupportedCmMember(path, member) => format!(
+        "{}: unsupported member, skipping: {}",
+        path.display(),
+        member.display()
+      ),
+      Self::InvalidMlb(path, msg) => format!("{}: invalid MLB file: {}", path.display(), msg),
+    }
+  }
+
+  /// The project file in which this error occurred.
+  pub fn path(&self) -> &Path {
+    match self {
+      Self::Io(path, _)
+      | Self::InvalidJson(path, _)
+      | Self::InvalidManifest(path, _)
+      | Self::InvalidCm(path, _)
+      | Self::UnsupportedCmMember(path, _)
+      | Self::InvalidMlb(path, _) => path,
+    }
+  }
+}
+
+/// Returns whether the path names a project file, as opposed to an SML source file.
+pub fn is_project_file(path: &Path) -> bool {
+  matches!(
+    path.extension().and_then(OsStr::to_str),
+    Some("json") | Some("cm") | Some("mlb")
+  )
+}
+
+/// Returns the project file in the directory `dir`, if there is one. This is the `millet.json`
+/// manifest if it exists, else `sources.cm` if it exists, else the first CM or MLB file in the
+/// directory by name.
+pub fn find(dir: &Path) -> Option<PathBuf> {
+  for name in [json::FILE_NAME, cm::FILE_NAME].iter() {
+    let path = dir.join(name);
+    if path.is_file() {
+      return Some(path);
+    }
+  }
+  let mut project_files: Vec<_> = std::fs::read_dir(dir)
+    .ok()?
+    .filter_map(|entry| Some(entry.ok()?.path()))
+    .filter(|path| {
+      let ext = path.extension().and_then(OsStr::to_str);
+      matches!(ext, Some("cm") | Some("mlb")) && path.is_file()
+    })
+    .collect();
+  project_files.sort();
+  project_files.into_iter().next()
+}
+
+/// A project, as described by a project file.
+#[derive(Debug)]
+pub struct Project {
+  /// The SML source files, in the order they should be checked.
+  pub files: Vec<PathBuf>,
+  /// The MLB files, if the project file was an MLB file. If this is `None`, each file should be
+  /// checked with everything declared in the files before it in scope. Otherwise, use `Mlb::ck`.
+  pub mlb: Option<mlb::Mlb>,
+  /// Problems with the project which did not stop it from being read, like members which were
+  /// skipped because they are not SML files.
+  pub warnings: Vec<Error>,
+}
+
+/// Returns the project described by the project file at `path`, which is either a `millet.json`
+/// manifest, a CM file, or an MLB file. Relative paths are resolved against the directory
+/// containing the project file which mentions them. `read` is used to read the project file, and
+/// any other project files it references.
+pub fn get<F>(path: &Path, mut read: F) -> Result<Project, Error>
+where
+  F: FnMut(&Path) -> std::io::Result<String>,
+{
+  let mut files = Vec::new();
+  let mut mlb = None;
+  let mut warnings = Vec::new();
+  match path.extension().and_then(OsStr::to_str) {
+    Some("cm") => cm::get(
+      path,
+      &mut read,
+      &mut HashSet::new(),
+      &mut files,
+      &mut warnings,
+    )?,
+    Some("mlb") => mlb = Some(mlb::get(path, &mut read, &mut files)?),
+    _ => {
+      let contents = read(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+      let dir = path.parent().unwrap_or_else(|| Path::new(""));
+      files = json::get(path, &contents)?
+        .into_iter()
+        .map(|x| join(dir, &x))
+        .collect();
+    }
+  }
+  Ok(Project {
+    files,
+    mlb,
+    warnings,
+  })
+}
+
+/// Joins `path` onto `dir`, removing `.` and `..` components where possible, so that the same file
+/// mentioned by different project files gets the same path.
+fn join(dir: &Path, path: &Path) -> PathBuf {
+  let mut ret = PathBuf::new();
+  for c in dir.join(path).components() {
+    match c {
+      Component::CurDir => {}
+      Component::ParentDir => match ret.components().next_back() {
+        Some(Component::Normal(_)) => {
+          ret.pop();
+        }
+        Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+        _ => ret.push(c),
+      },
+      _ => ret.push(c),
+    }
+  }
+  ret
+}
+
+#[test]
+fn test_join() {
+  let join = |dir: &str, path: &str| join(Path::new(dir), Path::new(path));
+  assert_eq!(join("/a/b", "../c/./d.sml"), PathBuf::from("/a/c/d.sml"));
+  assert_eq!(join("a", "../../b.sml"), PathBuf::from("../b.sml"));
+  assert_eq!(join("", "b.sml"), PathBuf::from("b.sml"));
+}
@@ -2,19 +2,35 @@
 
 use crate::ast::{Cases, DatBind, Dec, ExBindInner, Exp, Label, Long, TyBind};
 use crate::intern::StrRef;
-use crate::loc::Located;
+use crate::loc::{Loc, Located};
 use crate::statics::ck::util::{
   env_ins, env_merge, generalize, get_env, get_ty_sym, get_val_info, insert_ty_vars, instantiate,
 };
 use crate::statics::ck::{exhaustive, pat, ty};
 use crate::statics::types::{
-  Cx, Env, Error, Item, Pat, Result, State, StrEnv, Ty, TyEnv, TyInfo, TyScheme, TyVar, Tys,
-  ValEnv, ValInfo,
+  Cx, Def, Env, Error, Item, MissingArms, Pat, Result, State, StrEnv, Ty, TyEnv, TyInfo, TyScheme,
+  TyVar, ValEnv, ValInfo,
 };
 use maplit::btreemap;
 use std::collections::{BTreeMap, HashMap, HashSet};
 
-fn ck_exp(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
+/// Returns the type of `exp`. If there was an error when checking `exp`, records the error and
+/// returns a fresh type variable as the type, which allows checking to continue.
+fn ck_exp(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Ty {
+  match ck_exp_inner(cx, st, exp) {
+    Ok(ty) => {
+      st.record_ty(exp.loc, &ty);
+      ty
+    }
+    Err(e) => {
+      st.err(e);
+      Ty::Var(st.new_ty_var(false))
+    }
+  }
+}
+
+fn ck_exp_inner(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
+  st.record_scope(exp.loc, &cx.env, true);
   // The special constants are as per SML Definition (1). Note that SML Definition (5) is handled by
   // the parser and SML Definition (7) is handled by having atomic and non-atomic expressions be
   // part of the same enum.
@@ -28,7 +44,8 @@ fn ck_exp(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
     // the comment on this rule: "The instantiation of type schemes allows different occurrences of
     // a single longvid to assume different types."
     Exp::LongVid(vid) => {
-      let val_info = get_val_info(get_env(&cx.env, vid)?, vid.last)?;
+      let env = get_env(st, &cx.env, vid)?;
+      let val_info = get_val_info(st, env, vid.last)?;
       Ok(instantiate(st, &val_info.ty_scheme))
     }
     // SML Definition (3)
@@ -36,7 +53,7 @@ fn ck_exp(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
       let mut ty_rows = BTreeMap::new();
       // SML Definition (6)
       for row in rows {
-        let ty = ck_exp(cx, st, &row.val)?;
+        let ty = ck_exp(cx, st, &row.val);
         if ty_rows.insert(row.lab.val, ty).is_some() {
           return Err(row.lab.loc.wrap(Error::DuplicateLabel(row.lab.val)));
         }
@@ -48,7 +65,7 @@ fn ck_exp(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
     Exp::Tuple(exps) => {
       let mut ty_rows = BTreeMap::new();
       for (idx, exp) in exps.iter().enumerate() {
-        let ty = ck_exp(cx, st, exp)?;
+        let ty = ck_exp(cx, st, exp);
         assert!(ty_rows.insert(Label::tuple(idx), ty).is_none());
       }
       Ok(Ty::Record(ty_rows))
@@ -57,8 +74,9 @@ fn ck_exp(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
     Exp::List(exps) => {
       let elem = Ty::Var(st.new_ty_var(false));
       for exp in exps {
-        let ty = ck_exp(cx, st, exp)?;
-        st.unify(exp.loc, elem.clone(), ty)?;
+        let ty = ck_exp(cx, st, exp);
+        let res = st.unify(exp.loc, elem.clone(), ty);
+        st.recover(res);
       }
       Ok(Ty::list(elem))
     }
@@ -66,19 +84,20 @@ fn ck_exp(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
     Exp::Sequence(exps) => {
       let mut ret = None;
       for exp in exps {
-        ret = Some(ck_exp(cx, st, exp)?);
+        ret = Some(ck_exp(cx, st, exp));
       }
       Ok(ret.unwrap())
     }
     // SML Definition (4)
     Exp::Let(dec, exps) => {
       let gen_syms = st.generated_syms();
-      let env = ck(cx, st, dec)?;
+      let env = ck(cx, st, dec);
+      let env = st.recover(env);
       let mut cx = cx.clone();
       cx.o_plus(env);
       let mut last = None;
       for exp in exps {
-        last = Some((exp.loc, ck_exp(&cx, st, exp)?));
+        last = Some((exp.loc, ck_exp(&cx, st, exp)));
       }
       let (loc, mut ty) = last.unwrap();
       ty.apply(&st.subst);
@@ -89,8 +108,8 @@ fn ck_exp(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
     }
     // SML Definition (8)
     Exp::App(func, arg) => {
-      let func_ty = ck_exp(cx, st, func)?;
-      let arg_ty = ck_exp(cx, st, arg)?;
+      let func_ty = ck_exp(cx, st, func);
+      let arg_ty = ck_exp(cx, st, arg);
       // we don't actually _need_ to case on func_ty, since the Var case is actually correct for
       // _all_ types. we just do this to produce better error messages in the Record and Ctor cases.
       match func_ty {
@@ -114,10 +133,10 @@ fn ck_exp(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
     // SML Definition (8). Infix application is the same as `op`ing the infix operator and applying
     // it to a tuple (lhs, rhs).
     Exp::InfixApp(lhs, func, rhs) => {
-      let val_info = get_val_info(&cx.env, *func)?;
+      let val_info = get_val_info(st, &cx.env, *func)?;
       let func_ty = instantiate(st, &val_info.ty_scheme);
-      let lhs_ty = ck_exp(cx, st, lhs)?;
-      let rhs_ty = ck_exp(cx, st, rhs)?;
+      let lhs_ty = ck_exp(cx, st, lhs);
+      let rhs_ty = ck_exp(cx, st, rhs);
       let ret_ty = Ty::Var(st.new_ty_var(false));
       let arrow_ty = Ty::Arrow(Ty::pair(lhs_ty, rhs_ty).into(), ret_ty.clone().into());
       st.unify(exp.loc, func_ty, arrow_ty)?;
@@ -125,77 +144,128 @@ fn ck_exp(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
     }
     // SML Definition (9)
     Exp::Typed(inner, ty) => {
-      let exp_ty = ck_exp(cx, st, inner)?;
-      let ty_ty = ty::ck(cx, &st.tys, ty)?;
+      let exp_ty = ck_exp(cx, st, inner);
+      let ty_ty = ty::ck(cx, st, ty)?;
       st.unify(exp.loc, ty_ty, exp_ty.clone())?;
       Ok(exp_ty)
     }
     // SML Definition Appendix A - boolean operators are sugar for `if`
     Exp::Andalso(lhs, rhs) | Exp::Orelse(lhs, rhs) => {
-      let lhs_ty = ck_exp(cx, st, lhs)?;
-      let rhs_ty = ck_exp(cx, st, rhs)?;
-      st.unify(lhs.loc, Ty::BOOL, lhs_ty)?;
+      let lhs_ty = ck_exp(cx, st, lhs);
+      let rhs_ty = ck_exp(cx, st, rhs);
+      let res = st.unify(lhs.loc, Ty::BOOL, lhs_ty);
+      st.recover(res);
       st.unify(rhs.loc, Ty::BOOL, rhs_ty)?;
       Ok(Ty::BOOL)
     }
     // SML Definition (10)
     Exp::Handle(head, cases) => {
-      let head_ty = ck_exp(cx, st, head)?;
-      let (pats, arg_ty, res_ty) = ck_cases(cx, st, cases)?;
-      exhaustive::ck_handle(pats)?;
-      st.unify(exp.loc, Ty::EXN, arg_ty)?;
+      let head_ty = ck_exp(cx, st, head);
+      let (pats, arg_ty, res_ty) = ck_cases(cx, st, cases);
+      if let Some(pats) = pats {
+        let res = exhaustive::ck_handle(pats);
+        st.recover(res);
+      }
+      let res = st.unify(exp.loc, Ty::EXN, arg_ty);
+      st.recover(res);
       st.unify(exp.loc, head_ty.clone(), res_ty)?;
       Ok(head_ty)
     }
     // SML Definition (11)
     Exp::Raise(exp) => {
-      let exp_ty = ck_exp(cx, st, exp)?;
+      let exp_ty = ck_exp(cx, st, exp);
       st.unify(exp.loc, Ty::EXN, exp_ty)?;
       Ok(Ty::Var(st.new_ty_var(false)))
     }
     // SML Definition Appendix A - `if` is sugar for casing
     Exp::If(cond, then_e, else_e) => {
-      let cond_ty = ck_exp(cx, st, cond)?;
-      let then_ty = ck_exp(cx, st, then_e)?;
-      let else_ty = ck_exp(cx, st, else_e)?;
-      st.unify(cond.loc, Ty::BOOL, cond_ty)?;
+      let cond_ty = ck_exp(cx, st, cond);
+      let then_ty = ck_exp(cx, st, then_e);
+      let else_ty = ck_exp(cx, st, else_e);
+      let res = st.unify(cond.loc, Ty::BOOL, cond_ty);
+      st.recover(res);
       st.unify(exp.loc, then_ty.clone(), else_ty)?;
       Ok(then_ty)
     }
     Exp::While(..) => Err(exp.loc.wrap(Error::Todo("`while`"))),
     // SML Definition Appendix A - `case` is sugar for application to a `fn`
     Exp::Case(head, cases) => {
-      let head_ty = ck_exp(cx, st, head)?;
-      let (pats, arg_ty, res_ty) = ck_cases(cx, st, cases)?;
-      exhaustive::ck_match(pats, exp.loc)?;
-      st.unify(exp.loc, head_ty, arg_ty)?;
+      let head_ty = ck_exp(cx, st, head);
+      let (pats, arg_ty, res_ty) = ck_cases(cx, st, cases);
+      if let Some(pats) = pats {
+        let last_arm = cases.arms.last().unwrap().pat.loc;
+        ck_match(st, pats, exp.loc, last_arm, None);
+      }
+      let res = st.unify(exp.loc, head_ty, arg_ty);
+      st.recover(res);
       Ok(res_ty)
     }
     // SML Definition (12)
     Exp::Fn(cases) => {
-      let (pats, arg_ty, res_ty) = ck_cases(cx, st, cases)?;
-      exhaustive::ck_match(pats, exp.loc)?;
+      let (pats, arg_ty, res_ty) = ck_cases(cx, st, cases);
+      if let Some(pats) = pats {
+        let last_arm = cases.arms.last().unwrap().pat.loc;
+        ck_match(st, pats, exp.loc, last_arm, None);
+      }
       Ok(Ty::Arrow(arg_ty.into(), res_ty.into()))
     }
   }
 }
 
-/// SML Definition (13)
-fn ck_cases(cx: &Cx, st: &mut State, cases: &Cases<StrRef>) -> Result<(Vec<Located<Pat>>, Ty, Ty)> {
+/// Checks the match at `loc`, whose last arm starts at `last_arm`, is exhaustive and has no
+/// unreachable patterns. If it is not exhaustive, records the arms missing from it. `fun` is the
+/// name and number of arguments of the function, if the match is a `fun`.
+fn ck_match(
+  st: &mut State,
+  pats: Vec<Located<Pat>>,
+  loc: Loc,
+  last_arm: Loc,
+  fun: Option<(StrRef, usize)>,
+) {
+  let res = exhaustive::ck_match(&st.tys, pats, loc);
+  if let Err(e) = &res {
+    if let Error::NonExhaustiveMatch(pats) = &e.val {
+      st.record_missing_arms(MissingArms {
+        loc,
+        last_arm,
+        fun,
+        pats: pats.clone(),
+      });
+    }
+  }
+  st.recover(res);
+}
+
+/// SML Definition (13). The patterns are `None` if there was an error when checking any of them, in
+/// which case we should not try to check them for exhaustiveness.
+fn ck_cases(cx: &Cx, st: &mut State, cases: &Cases<StrRef>) -> (Option<Vec<Located<Pat>>>, Ty, Ty) {
   let arg_ty = Ty::Var(st.new_ty_var(false));
   let res_ty = Ty::Var(st.new_ty_var(false));
-  let mut pats = Vec::with_capacity(cases.arms.len());
+  let mut pats = Some(Vec::with_capacity(cases.arms.len()));
   // SML Definition (14)
   for arm in cases.arms.iter() {
-    let (val_env, pat_ty, pat) = pat::ck(cx, st, &arm.pat)?;
-    pats.push(arm.pat.loc.wrap(pat));
     let mut cx = cx.clone();
-    cx.env.val_env.extend(val_env);
-    let exp_ty = ck_exp(&cx, st, &arm.exp)?;
-    st.unify(arm.pat.loc, arg_ty.clone(), pat_ty)?;
-    st.unify(arm.exp.loc, res_ty.clone(), exp_ty)?;
+    let pat_ty = match pat::ck(&cx, st, &arm.pat) {
+      Ok((val_env, pat_ty, pat)) => {
+        if let Some(pats) = pats.as_mut() {
+          pats.push(arm.pat.loc.wrap(pat));
+        }
+        cx.env.val_env.extend(val_env);
+        pat_ty
+      }
+      Err(e) => {
+        st.err(e);
+        pats = None;
+        Ty::Var(st.new_ty_var(false))
+      }
+    };
+    let exp_ty = ck_exp(&cx, st, &arm.exp);
+    let res = st.unify(arm.pat.loc, arg_ty.clone(), pat_ty);
+    st.recover(res);
+    let res = st.unify(arm.exp.loc, res_ty.clone(), exp_ty);
+    st.recover(res);
   }
-  Ok((pats, arg_ty, res_ty))
+  (pats, arg_ty, res_ty)
 }
 
 /// Returns `Ok(())` iff `name` is not a forbidden binding name. TODO there are more of these in
@@ -216,25 +286,30 @@ fn ck_binding(name: Located<StrRef>) -> Result<()> {
 struct FunInfo {
   args: Vec<TyVar>,
   ret: TyVar,
+  def: Def,
+}
+
+impl FunInfo {
+  fn ty(&self) -> Ty {
+    self.args.iter().rev().fold(Ty::Var(self.ret), |ac, &tv| {
+      Ty::Arrow(Ty::Var(tv).into(), ac.into())
+    })
+  }
 }
 
 fn fun_infos_to_ve(fun_infos: &HashMap<StrRef, FunInfo>) -> ValEnv {
   fun_infos
     .iter()
     .map(|(&name, fun_info)| {
-      let ty = fun_info
-        .args
-        .iter()
-        .rev()
-        .fold(Ty::Var(fun_info.ret), |ac, &tv| {
-          Ty::Arrow(Ty::Var(tv).into(), ac.into())
-        });
-      (name, ValInfo::val(TyScheme::mono(ty)))
+      let mut val_info = ValInfo::val(TyScheme::mono(fun_info.ty()));
+      val_info.def = Some(fun_info.def);
+      (name, val_info)
     })
     .collect()
 }
 
 pub fn ck(cx: &Cx, st: &mut State, dec: &Located<Dec<StrRef>>) -> Result<Env> {
+  st.record_scope(dec.loc, &cx.env, false);
   match &dec.val {
     // SML Definition (15)
     Dec::Val(ty_vars, val_binds) => {
@@ -251,19 +326,32 @@ pub fn ck(cx: &Cx, st: &mut State, dec: &Located<Dec<StrRef>>) -> Result<Env> {
       for val_bind in val_binds {
         // SML Definition (26)
         if val_bind.rec {
-          return Err(dec.loc.wrap(Error::Todo("recursive val binds")));
+          st.err(dec.loc.wrap(Error::Todo("recursive val binds")));
+          continue;
         }
-        let (other, pat_ty, pat) = pat::ck(cx, st, &val_bind.pat)?;
+        let (other, pat_ty, pat) = match pat::ck(cx, st, &val_bind.pat) {
+          Ok(x) => x,
+          Err(e) => {
+            st.err(e);
+            // still check the expression to report any errors in it.
+            ck_exp(cx, st, &val_bind.exp);
+            continue;
+          }
+        };
         for &name in other.keys() {
-          ck_binding(val_bind.pat.loc.wrap(name))?;
+          let res = ck_binding(val_bind.pat.loc.wrap(name));
+          st.recover(res);
         }
-        let exp_ty = ck_exp(cx, st, &val_bind.exp)?;
-        st.unify(dec.loc, pat_ty.clone(), exp_ty)?;
-        exhaustive::ck_bind(pat, val_bind.pat.loc)?;
+        let exp_ty = ck_exp(cx, st, &val_bind.exp);
+        let res = st.unify(dec.loc, pat_ty.clone(), exp_ty);
+        st.recover(res);
+        let res = exhaustive::ck_bind(&st.tys, pat, val_bind.pat.loc);
+        st.recover(res);
         for (name, mut val_info) in other {
           generalize(cx, st, ty_vars, &mut val_info.ty_scheme);
           let name = val_bind.pat.loc.wrap(name);
-          env_ins(&mut val_env, name, val_info, Item::Val)?;
+          let res = env_ins(&mut val_env, name, val_info, Item::Val);
+          st.recover(res);
         }
       }
       Ok(val_env.into())
@@ -279,58 +367,95 @@ pub fn ck(cx: &Cx, st: &mut State, dec: &Located<Dec<StrRef>>) -> Result<Env> {
         &cx_cl
       };
       let mut fun_infos = HashMap::with_capacity(fval_binds.len());
+      // the `FValBind`s which are not duplicates of an earlier one.
+      let mut to_ck = Vec::with_capacity(fval_binds.len());
       for fval_bind in fval_binds {
         let first = fval_bind.cases.first().unwrap();
+        // copied from env_ins in util
+        if fun_infos.contains_key(&first.vid.val) {
+          let err = Error::Duplicate(Item::Val, first.vid.val);
+          st.err(first.vid.loc.wrap(err));
+          continue;
+        }
         let info = FunInfo {
           args: first.pats.iter().map(|_| st.new_ty_var(false)).collect(),
           ret: st.new_ty_var(false),
+          def: st.def(first.vid.loc),
         };
-        // copied from env_ins in util
-        if fun_infos.insert(first.vid.val, info).is_some() {
-          let err = Error::Duplicate(Item::Val, first.vid.val);
-          return Err(first.vid.loc.wrap(err));
-        }
+        fun_infos.insert(first.vid.val, info);
+        to_ck.push(fval_bind);
       }
-      for fval_bind in fval_binds {
+      for fval_bind in to_ck {
         let name = fval_bind.cases.first().unwrap().vid.val;
         let info = fun_infos.get(&name).unwrap();
         let mut arg_pats = Vec::with_capacity(fval_bind.cases.len());
+        // whether all the cases and their patterns checked ok, so we can check exhaustiveness.
+        let mut pats_ok = true;
         for case in fval_bind.cases.iter() {
           if name != case.vid.val {
             let err = Error::FunDecNameMismatch(name, case.vid.val);
-            return Err(case.vid.loc.wrap(err));
+            st.err(case.vid.loc.wrap(err));
+            pats_ok = false;
+            continue;
+          }
+          st.record_ty(case.vid.loc, &info.ty());
+          if case.vid.loc != info.def.loc {
+            st.use_def(case.vid.loc, Some(info.def));
           }
           if info.args.len() != case.pats.len() {
             let err = Error::FunDecWrongNumPats(info.args.len(), case.pats.len());
             let begin = case.pats.first().unwrap().loc;
             let end = case.pats.last().unwrap().loc;
-            return Err(begin.span(end).wrap(err));
+            st.err(begin.span(end).wrap(err));
+            pats_ok = false;
+            continue;
           }
           let mut pats_val_env = ValEnv::new();
           let mut arg_pat = Vec::with_capacity(info.args.len());
           for (pat, &tv) in case.pats.iter().zip(info.args.iter()) {
-            let (ve, pat_ty, new_pat) = pat::ck(cx, st, pat)?;
-            st.unify(pat.loc, Ty::Var(tv), pat_ty)?;
-            env_merge(&mut pats_val_env, ve, pat.loc, Item::Val)?;
+            let (ve, pat_ty, new_pat) = match pat::ck(cx, st, pat) {
+              Ok(x) => x,
+              Err(e) => {
+                st.err(e);
+                pats_ok = false;
+                continue;
+              }
+            };
+            let res = st.unify(pat.loc, Ty::Var(tv), pat_ty);
+            st.recover(res);
+            let res = env_merge(&mut pats_val_env, ve, pat.loc, Item::Val);
+            st.recover(res);
             arg_pat.push(new_pat);
           }
           let begin = case.pats.first().unwrap().loc;
           let end = case.pats.last().unwrap().loc;
-          arg_pats.push(begin.span(end).wrap(Pat::record(arg_pat)));
+          arg_pats.push(begin.span(end).wrap(Pat::tuple(arg_pat)));
           if let Some(ty) = &case.ret_ty {
-            let new_ty = ty::ck(cx, &st.tys, ty)?;
-            st.unify(ty.loc, Ty::Var(info.ret), new_ty)?;
+            match ty::ck(cx, st, ty) {
+              Ok(new_ty) => {
+                let res = st.unify(ty.loc, Ty::Var(info.ret), new_ty);
+                st.recover(res);
+              }
+              Err(e) => st.err(e),
+            }
           }
           let mut cx = cx.clone();
           // no dupe checking here - intentionally shadow.
           cx.env.val_env.extend(fun_infos_to_ve(&fun_infos));
           cx.env.val_env.extend(pats_val_env);
-          let body_ty = ck_exp(&cx, st, &case.body)?;
-          st.unify(case.body.loc, Ty::Var(info.ret), body_ty)?;
+          let body_ty = ck_exp(&cx, st, &case.body);
+          let res = st.unify(case.body.loc, Ty::Var(info.ret), body_ty);
+          st.recover(res);
+        }
+        if pats_ok {
+          let first = fval_bind.cases.first().unwrap();
+          let last = fval_bind.cases.last().unwrap();
+          let loc = first.vid.loc.span(last.body.loc);
+          // for infix functions, the name is not at the start of the clause.
+          let last_arm = last.vid.loc.min(last.pats.first().unwrap().loc);
+          let fun = Some((name, info.args.len()));
+          ck_match(st, arg_pats, loc, last_arm, fun);
         }
-        let begin = fval_bind.cases.first().unwrap().vid.loc;
-        let end = fval_bind.cases.last().unwrap().body.loc;
-        exhaustive::ck_match(arg_pats, begin.span(end))?;
       }
       let mut val_env = fun_infos_to_ve(&fun_infos);
       for val_info in val_env.values_mut() {
@@ -350,7 +475,7 @@ pub fn ck(cx: &Cx, st: &mut State, dec: &Located<Dec<StrRef>>) -> Result<Env> {
       Ok(env)
     }
     // SML Definition (18)
-    Dec::DatatypeCopy(ty_con, long) => ck_dat_copy(cx, &st.tys, *ty_con, long),
+    Dec::DatatypeCopy(ty_con, long) => ck_dat_copy(cx, st, *ty_con, long),
     // SML Definition (19)
     Dec::Abstype(..) => Err(dec.loc.wrap(Error::Todo("`abstype`"))),
     // SML Definition (20)
@@ -359,13 +484,18 @@ pub fn ck(cx: &Cx, st: &mut State, dec: &Located<Dec<StrRef>>) -> Result<Env> {
       for ex_bind in ex_binds {
         let val_info = match &ex_bind.inner {
           // SML Definition (30)
-          ExBindInner::Ty(ty) => match ty {
-            None => ValInfo::exn(),
-            Some(ty) => ValInfo::exn_fn(ty::ck(cx, &st.tys, ty)?),
-          },
+          ExBindInner::Ty(ty) => {
+            let mut val_info = match ty {
+              None => ValInfo::exn(),
+              Some(ty) => ValInfo::exn_fn(ty::ck(cx, st, ty)?),
+            };
+            val_info.def = Some(st.def(ex_bind.vid.loc));
+            val_info
+          }
           // SML Definition (31)
           ExBindInner::Long(vid) => {
-            let val_info = get_val_info(get_env(&cx.env, vid)?, vid.last)?;
+            let env = get_env(st, &cx.env, vid)?;
+            let val_info = get_val_info(st, env, vid.last)?;
             if !val_info.id_status.is_exn() {
               return Err(vid.loc().wrap(Error::ExnWrongIdStatus(val_info.id_status)));
             }
@@ -378,7 +508,8 @@ pub fn ck(cx: &Cx, st: &mut State, dec: &Located<Dec<StrRef>>) -> Result<Env> {
     }
     // SML Definition (21)
     Dec::Local(fst, snd) => {
-      let fst_env = ck(cx, st, fst)?;
+      let fst_env = ck(cx, st, fst);
+      let fst_env = st.recover(fst_env);
       let mut cx = cx.clone();
       cx.o_plus(fst_env);
       ck(&cx, st, snd)
@@ -387,7 +518,16 @@ pub fn ck(cx: &Cx, st: &mut State, dec: &Located<Dec<StrRef>>) -> Result<Env> {
     Dec::Open(longs) => {
       let mut env = Env::default();
       for long in longs {
-        env.extend(get_env(&cx.env, long)?.clone());
+        match get_env(st, &cx.env, long)?.str_env.get(&long.last.val) {
+          None => {
+            let err = Error::Undefined(Item::Struct, long.last.val);
+            return Err(long.last.loc.wrap(err));
+          }
+          Some(str_env) => {
+            st.use_def(long.last.loc, str_env.def);
+            env.extend(str_env.clone());
+          }
+        }
       }
       Ok(env)
     }
@@ -397,8 +537,13 @@ pub fn ck(cx: &Cx, st: &mut State, dec: &Located<Dec<StrRef>>) -> Result<Env> {
       let mut ret = Env::default();
       for dec in decs {
         cx.o_plus(ret.clone());
-        let env = ck(&cx, st, dec)?;
-        ret.extend(env);
+        let env = ck(&cx, st, dec);
+        ret.extend(st.recover(env));
+        if st.is_before_scope(dec.loc) {
+          let mut env = cx.env.clone();
+          env.extend(ret.clone());
+          st.scope = Some((env, false));
+        }
       }
       Ok(ret)
     }
@@ -419,7 +564,7 @@ fn ck_ty_binds(cx: &Cx, st: &mut State, ty_binds: &[TyBind<StrRef>]) -> Result<E
       insert_ty_vars(&mut cx_cl, st, &ty_bind.ty_vars)?;
       &cx_cl
     };
-    let ty = ty::ck(cx, &st.tys, &ty_bind.ty)?;
+    let ty = ty::ck(cx, st, &ty_bind.ty)?;
     let sym = st.new_sym(ty_bind.ty_con);
     env_ins(&mut ty_env.inner, ty_bind.ty_con, sym, Item::Ty)?;
     // TODO better equality checks
@@ -489,8 +634,8 @@ pub fn ck_dat_binds(mut cx: Cx, st: &mut State, dat_binds: &[DatBind<StrRef>]) -
   }
   // SML Definition (28), SML Definition (81)
   for (dat_bind, sym) in dat_binds.iter().zip(syms) {
-    // note that we have to `get` here and then `get_mut` again later because of the borrow checker.
-    let ty_fcn = &st.tys.get(&sym).ty_fcn;
+    // note that we have to clone here and then `get_mut` again later because of the borrow checker.
+    let ty_fcn = st.tys.get(&sym).ty_fcn.clone();
     let mut cx_cl;
     let cx = if dat_bind.ty_vars.is_empty() {
       &cx
@@ -525,15 +670,16 @@ pub fn ck_dat_binds(mut cx: Cx, st: &mut State, dat_binds: &[DatBind<StrRef>]) -
         // if there is an `of t`, then the type of the ctor is `t -> T`. we must also update whether
         // `T` respects equality based on whether `t` does. TODO this doesn't handle the equality
         // check correctly.
-        let t = ty::ck(&cx, &st.tys, arg_ty)?;
+        let t = ty::ck(&cx, st, arg_ty)?;
         equality = equality && t.is_equality(&st.tys);
         ty = Ty::Arrow(t.into(), ty.into());
       }
-      let val_info = ValInfo::ctor(TyScheme {
+      let mut val_info = ValInfo::ctor(TyScheme {
         ty_vars: ty_fcn.ty_vars.clone(),
         ty,
         overload: None,
       });
+      val_info.def = Some(st.def(con_bind.vid.loc));
       // insert the `ValInfo` into the _overall_ `ValEnv` with dupe checking.
       env_ins(&mut val_env, con_bind.vid, val_info.clone(), Item::Val)?;
       // _also_ insert the `ValInfo` into the `DatBind`-specific `ValEnv`, but this time dupe
@@ -548,18 +694,20 @@ pub fn ck_dat_binds(mut cx: Cx, st: &mut State, dat_binds: &[DatBind<StrRef>]) -
     ty_env,
     val_env,
     str_env: StrEnv::new(),
+    def: None,
   })
 }
 
 /// SML Definition (18), SML Definition (72)
 pub fn ck_dat_copy(
   cx: &Cx,
-  tys: &Tys,
+  st: &mut State,
   ty_con: Located<StrRef>,
   long: &Long<StrRef>,
 ) -> Result<Env> {
-  let sym = get_ty_sym(get_env(&cx.env, long)?, long.last)?;
-  let val_env = tys.get(&sym).val_env.clone();
+  let env = get_env(st, &cx.env, long)?;
+  let sym = get_ty_sym(st, env, long.last)?;
+  let val_env = st.tys.get(&sym).val_env.clone();
   if val_env.is_empty() {
     return Err(long.loc().wrap(Error::DatatypeCopyNotDatatype));
   }
@@ -569,5 +717,6 @@ pub fn ck_dat_copy(
       inner: btreemap![ty_con.val => sym],
     },
     val_env,
+    def: None,
   })
 }
@@ -1,5 +1,6 @@
 //! Check whether an environment enriches another environment.
 
+use crate::intern::StrRef;
 use crate::loc::Loc;
 use crate::statics::ty_rzn::TyRealization;
 use crate::statics::types::{
@@ -62,20 +63,35 @@ fn ck_impl(cx: Cx, got: &Env, want: &Env) -> Result<()> {
   for (name, want) in want.val_env.iter() {
     match got.val_env.get(name) {
       None => return Err(cx.loc.wrap(Error::Undefined(Item::Val, *name))),
-      Some(got) => ck_val_info(cx, got, want)?,
+      Some(got) => ck_val_info(cx, *name, got, want)?,
     }
   }
   Ok(())
 }
 
-fn ck_val_info(cx: Cx, got: &ValInfo, want: &ValInfo) -> Result<()> {
+fn ck_val_info(cx: Cx, name: StrRef, got: &ValInfo, want: &ValInfo) -> Result<()> {
   if want.id_status != got.id_status && !want.id_status.is_val() {
     // TODO improve this error to mention that it's also ok if want is a value?
     let err = Error::IdStatusMismatch(want.id_status, got.id_status);
     return Err(cx.loc.wrap(err));
   }
-  ck_generalizes(cx, want.ty_scheme.clone(), got.ty_scheme.clone())?;
-  Ok(())
+  let e = match ck_generalizes(cx, want.ty_scheme.clone(), got.ty_scheme.clone()) {
+    Ok(()) => return Ok(()),
+    Err(e) => e,
+  };
+  // if the types unify when the type variables in want are not held fixed, then the problem is
+  // specifically that got is less general than want, so report that instead.
+  let mut want_ty = want.ty_scheme.ty.clone();
+  let mut got_ty = got.ty_scheme.ty.clone();
+  cx.ty_rzn.get_ty(&mut want_ty);
+  cx.ty_rzn.get_ty(&mut got_ty);
+  let unifies = Subst::default()
+    .unify(cx.loc, cx.tys, want_ty.clone(), got_ty.clone())
+    .is_ok();
+  if want.ty_scheme.ty_vars.is_empty() || !unifies {
+    return Err(e);
+  }
+  Err(cx.loc.wrap(Error::NotGeneral(name, want_ty, got_ty)))
 }
 
 fn ck_ty_info(cx: Cx, got: &TyInfo, want: &TyInfo) -> Result<()> {
@@ -112,6 +128,10 @@ fn ck_ty_fcn_eq(cx: Cx, got: &TyFcn, want: &TyFcn) -> Result<()> {
 }
 
 /// Returns `Ok(())` iff want generalizes got as per the Definition.
+///
+/// That is, `want.ty` must be an instance of `got`. We check this by unifying the two types while
+/// holding the type variables bound by want fixed, so that only those bound by got may be
+/// substituted.
 fn ck_generalizes(cx: Cx, mut want: TyScheme, mut got: TyScheme) -> Result<()> {
   let want_free_tvs = want.free_ty_vars();
   for tv in got.ty_vars.iter() {
@@ -121,5 +141,9 @@ fn ck_generalizes(cx: Cx, mut want: TyScheme, mut got: TyScheme) -> Result<()> {
   }
   cx.ty_rzn.get_ty(&mut want.ty);
   cx.ty_rzn.get_ty(&mut got.ty);
-  Subst::default().unify(cx.loc, &cx.tys, want.ty, got.ty)
+  let mut subst = Subst::default();
+  for &tv in want.ty_vars.iter() {
+    subst.insert_bound(tv);
+  }
+  subst.unify(cx.loc, &cx.tys, want.ty, got.ty)
 }
@@ -14,25 +14,39 @@
 //!   be the same length, have a single list of structs with two fields).
 //! - We have a few more options for Con because we don't want to represent things like numbers as
 //!   strings.
+//! - We remember the descriptions of the values we find that are not matched, so we can report the
+//!   missing patterns.
 
+use crate::intern::StrRef;
 use crate::loc::{Loc, Located};
-use crate::statics::types::{Con, Error, Pat, Result, Span};
+use crate::statics::types::{Con, Error, Pat, Result, Span, Ty, Tys};
 use std::collections::HashSet;
 
-/// Returns `Ok(())` iff the pats are exhaustive and not redundant.
-pub fn ck_match(pats: Vec<Located<Pat>>, loc: Loc) -> Result<()> {
+/// The most missing patterns to report for a match.
+const MAX_MISSING: usize = 20;
+
+/// Returns `Ok(())` iff the pats are exhaustive and not redundant. The `Tys` are used to find the
+/// constructors missing from the pats.
+pub fn ck_match(tys: &Tys, pats: Vec<Located<Pat>>, loc: Loc) -> Result<()> {
   match ck(pats) {
     Res::Exhaustive => Ok(()),
-    Res::NonExhaustive => Err(loc.wrap(Error::NonExhaustiveMatch)),
+    Res::NonExhaustive(ds) => {
+      let mut pats: Vec<_> = ds.into_iter().flat_map(|d| missing_pats(tys, d)).collect();
+      pats.truncate(MAX_MISSING);
+      Err(loc.wrap(Error::NonExhaustiveMatch(pats)))
+    }
     Res::Unreachable(loc) => Err(loc.wrap(Error::UnreachablePattern)),
   }
 }
 
 /// Returns `Ok(())` iff the singular pat is exhaustive.
-pub fn ck_bind(pat: Pat, loc: Loc) -> Result<()> {
+pub fn ck_bind(tys: &Tys, pat: Pat, loc: Loc) -> Result<()> {
   match ck(vec![loc.wrap(pat)]) {
     Res::Exhaustive => Ok(()),
-    Res::NonExhaustive => Err(loc.wrap(Error::NonExhaustiveBinding)),
+    Res::NonExhaustive(mut ds) => {
+      let pat = missing_pats(tys, ds.swap_remove(0)).swap_remove(0);
+      Err(loc.wrap(Error::NonExhaustiveBinding(pat)))
+    }
     Res::Unreachable(_) => unreachable!(),
   }
 }
@@ -40,7 +54,7 @@ pub fn ck_bind(pat: Pat, loc: Loc) -> Result<()> {
 /// Returns `Ok(())` iff the pats are not redundant.
 pub fn ck_handle(pats: Vec<Located<Pat>>) -> Result<()> {
   match ck(pats) {
-    Res::Exhaustive | Res::NonExhaustive => Ok(()),
+    Res::Exhaustive | Res::NonExhaustive(_) => Ok(()),
     Res::Unreachable(loc) => Err(loc.wrap(Error::UnreachablePattern)),
   }
 }
@@ -92,10 +106,15 @@ struct Arg {
 /// The work list. The back of the list is the next item to be processed (it's a stack).
 type Work = Vec<WorkItem>;
 
-/// The context, passed along through most of the main functions. This is an set of the locations of
-/// the patterns of the match. As we determine a pattern is reachable, we remove its `Loc` from this
-/// set. At the end, the set contains the locations of all unreachable patterns.
-type Cx = HashSet<Loc>;
+/// The context, passed along through most of the main functions.
+struct Cx {
+  /// The locations of the patterns of the match. As we determine a pattern is reachable, we remove
+  /// its `Loc` from this set. At the end, the set contains the locations of all unreachable
+  /// patterns.
+  unreachable: HashSet<Loc>,
+  /// Descriptions of the values we found that no pattern matches, in the order we found them.
+  missing: Vec<Desc>,
+}
 
 /// The patterns, created from an `into_iter()` call on the passed-in `Vec<Located<Pat>>`.
 type Pats = std::vec::IntoIter<Located<Pat>>;
@@ -104,23 +123,91 @@ type Pats = std::vec::IntoIter<Located<Pat>>;
 enum Res {
   /// They were exhaustive.
   Exhaustive,
-  /// They were not exhaustive.
-  NonExhaustive,
+  /// They were not exhaustive. The Descs describe values they do not match. There is at least one.
+  NonExhaustive(Vec<Desc>),
   /// There was a pattern which can never be reached.
   Unreachable(Loc),
 }
 
 /// The main function, which the exported functions ultimately call.
 fn ck(pats: Vec<Located<Pat>>) -> Res {
-  let mut cx: Cx = pats.iter().map(|x| x.loc).collect();
+  let mut cx = Cx {
+    unreachable: pats.iter().map(|x| x.loc).collect(),
+    missing: Vec::new(),
+  };
   if fail(&mut cx, Desc::Neg(vec![]), pats.into_iter()) {
     // Must choose the minimum loc to get the first unreachable pattern.
-    match cx.into_iter().min() {
+    match cx.unreachable.into_iter().min() {
       None => Res::Exhaustive,
       Some(loc) => Res::Unreachable(loc),
     }
   } else {
-    Res::NonExhaustive
+    Res::NonExhaustive(cx.missing)
+  }
+}
+
+/// Returns patterns that together match the values described by the `Desc`. There may be too many
+/// to return all of them, in which case at most `MAX_MISSING` are returned.
+fn missing_pats(tys: &Tys, d: Desc) -> Vec<Pat> {
+  match d {
+    Desc::Pos(con, descs) => {
+      // every combination of the patterns for each argument.
+      let mut args_list = vec![Vec::with_capacity(descs.len())];
+      for d in descs {
+        let pats = missing_pats(tys, d);
+        args_list = args_list
+          .into_iter()
+          .flat_map(|args| {
+            pats.iter().map(move |pat| {
+              let mut args = args.clone();
+              args.push(pat.clone());
+              args
+            })
+          })
+          .take(MAX_MISSING)
+          .collect();
+      }
+      args_list
+        .into_iter()
+        .map(|args| Pat::Con(con.clone(), args))
+        .collect()
+    }
+    Desc::Neg(cons) => match cons.first() {
+      Some(Con::Char(_)) => vec![(0..=u8::MAX)
+        .map(Con::Char)
+        .find(|c| !cons.contains(c))
+        .map_or(Pat::Anything, Pat::zero)],
+      Some(&Con::Ctor(_, sym, span @ Span::Finite(_))) => {
+        let ret: Vec<_> = tys
+          .get(&sym)
+          .val_env
+          .iter()
+          .filter(|(&name, _)| {
+            !cons
+              .iter()
+              .any(|c| matches!(*c, Con::Ctor(other, _, _) if other == name))
+          })
+          .map(|(&name, val_info)| {
+            let con = Con::Ctor(name, sym, span);
+            match val_info.ty_scheme.ty {
+              // So that it is shown infix.
+              Ty::Arrow(_, _) if name == StrRef::CONS => {
+                Pat::Con(con, vec![Pat::tuple(vec![Pat::Anything, Pat::Anything])])
+              }
+              Ty::Arrow(_, _) => Pat::Con(con, vec![Pat::Anything]),
+              _ => Pat::zero(con),
+            }
+          })
+          .collect();
+        if ret.is_empty() {
+          vec![Pat::Anything]
+        } else {
+          ret
+        }
+      }
+      // Either nothing is known, or there are too many other values to list.
+      _ => vec![Pat::Anything],
+    },
   }
 }
 
@@ -149,17 +236,17 @@ fn build_desc(mut d: Desc, work: Work) -> Desc {
 }
 
 /// Statically match a `Con` against a `Desc`.
-fn static_match(con: Con, d: &Desc) -> StaticMatch {
+fn static_match(con: &Con, d: &Desc) -> StaticMatch {
   match d {
     Desc::Pos(c, _) => {
-      if *c == con {
+      if c == con {
         StaticMatch::Yes
       } else {
         StaticMatch::No
       }
     }
     Desc::Neg(cons) => {
-      if cons.iter().any(|c| c == &con) {
+      if cons.contains(con) {
         StaticMatch::No
       } else if con.span() == Span::Finite(cons.len() + 1) {
         // This is the last con.
@@ -175,7 +262,11 @@ fn static_match(con: Con, d: &Desc) -> StaticMatch {
 /// match was exhaustive.
 fn fail(cx: &mut Cx, d: Desc, mut pats: Pats) -> bool {
   match pats.next() {
-    None => false,
+    None => {
+      // `d` describes values that none of the pats match.
+      cx.missing.push(d);
+      false
+    }
     Some(pat) => do_match(cx, pat, d, vec![], pats),
   }
 }
@@ -185,7 +276,7 @@ fn fail(cx: &mut Cx, d: Desc, mut pats: Pats) -> bool {
 fn succeed(cx: &mut Cx, loc: Loc, mut work: Work, pats: Pats) -> bool {
   match work.pop() {
     None => {
-      cx.remove(&loc);
+      cx.unreachable.remove(&loc);
       true
     }
     Some(mut item) => match item.args.pop() {
@@ -235,13 +326,17 @@ fn succeed_with(
 fn do_match(cx: &mut Cx, pat: Located<Pat>, d: Desc, work: Work, pats: Pats) -> bool {
   match pat.val {
     Pat::Anything => succeed(cx, pat.loc, augment(work, d), pats),
-    Pat::Con(con, args) => match static_match(con, &d) {
+    Pat::Con(con, args) => match static_match(&con, &d) {
       StaticMatch::Yes => succeed_with(cx, pat.loc, work, con, args, d, pats),
       StaticMatch::No => fail(cx, build_desc(d, work), pats),
       StaticMatch::Maybe(mut cons) => {
-        cons.push(con);
-        succeed_with(cx, pat.loc, work.clone(), con, args, d, pats.clone())
-          && fail(cx, build_desc(Desc::Neg(cons), work), pats)
+        cons.push(con.clone());
+        let exhaustive = succeed_with(cx, pat.loc, work.clone(), con, args, d, pats.clone());
+        // even if we already know this is not exhaustive, keep going to find more missing values.
+        if !exhaustive && cx.missing.len() >= MAX_MISSING {
+          return false;
+        }
+        fail(cx, build_desc(Desc::Neg(cons), work), pats) && exhaustive
       }
     },
   }
@@ -12,6 +12,12 @@ use maplit::btreemap;
 use std::collections::BTreeMap;
 
 pub fn ck(cx: &Cx, st: &mut State, pat: &Located<AstPat<StrRef>>) -> Result<(ValEnv, Ty, Pat)> {
+  let ret = ck_inner(cx, st, pat)?;
+  st.record_ty(pat.loc, &ret.1);
+  Ok(ret)
+}
+
+fn ck_inner(cx: &Cx, st: &mut State, pat: &Located<AstPat<StrRef>>) -> Result<(ValEnv, Ty, Pat)> {
   // Wildcard is by SML Definition (32), special constants are by SML Definition (33). Additionally,
   // SML Definition (37) is handled by the parser, and SML Definition (40) is handed because atomic
   // and non-atomic Pats are both in the same enum.
@@ -24,32 +30,33 @@ pub fn ck(cx: &Cx, st: &mut State, pat: &Located<AstPat<StrRef>>) -> Result<(Val
     AstPat::String(s) => Ok((ValEnv::new(), Ty::STRING, Pat::zero(Con::String(*s)))),
     AstPat::Char(c) => Ok((ValEnv::new(), Ty::CHAR, Pat::zero(Con::Char(*c)))),
     AstPat::LongVid(vid) => {
-      let ty_scheme = get_env(&cx.env, vid)?
-        .val_env
-        .get(&vid.last.val)
-        .and_then(|val_info| {
-          if val_info.id_status.is_val() {
-            None
-          } else {
-            Some(&val_info.ty_scheme)
-          }
-        });
-      match ty_scheme {
+      let val_info = get_env(st, &cx.env, vid)?.val_env.get(&vid.last.val);
+      match val_info.filter(|val_info| !val_info.id_status.is_val()) {
+        // only an unqualified identifier may be a variable.
+        None if !vid.structures.is_empty() => {
+          let err = match val_info {
+            None => Error::Undefined(Item::Val, vid.last.val),
+            Some(_) => Error::PatWrongIdStatus,
+          };
+          Err(vid.last.loc.wrap(err))
+        }
         // SML Definition (34)
         None => {
           let a = Ty::Var(st.new_ty_var(false));
-          let val_info = ValInfo::val(TyScheme::mono(a.clone()));
+          let mut val_info = ValInfo::val(TyScheme::mono(a.clone()));
+          val_info.def = Some(st.def(vid.last.loc));
           Ok((btreemap![vid.last.val => val_info], a, Pat::Anything))
         }
         // SML Definition (35)
-        Some(ty_scheme) => {
-          let ty = instantiate(st, ty_scheme);
+        Some(val_info) => {
+          st.use_def(vid.last.loc, val_info.def);
+          let ty = instantiate(st, &val_info.ty_scheme);
           let sym = match ty {
             Ty::Ctor(_, sym) => sym,
             _ => return Err(pat.loc.wrap(Error::PatNotConsTy(ty))),
           };
           let span = get_span(&st.tys, sym);
-          let pat = Pat::zero(Con::Ctor(vid.last.val, span));
+          let pat = Pat::zero(Con::Ctor(vid.last.val, sym, span));
           Ok((ValEnv::new(), ty, pat))
         }
       }
@@ -72,7 +79,6 @@ pub fn ck(cx: &Cx, st: &mut State, pat: &Located<AstPat<StrRef>>) -> Result<(Val
         env_merge(&mut val_env, other_ve, row.val.loc, Item::Val)?;
         assert!(ty_rows.insert(row.lab.val, ty).is_none());
       }
-      let new_pats: Vec<_> = new_pats.into_iter().map(|(_, pat)| pat).collect();
       let pat = Pat::record(new_pats);
       Ok((val_env, Ty::Record(ty_rows), pat))
     }
@@ -87,7 +93,7 @@ pub fn ck(cx: &Cx, st: &mut State, pat: &Located<AstPat<StrRef>>) -> Result<(Val
         assert!(ty_rows.insert(Label::tuple(idx), ty).is_none());
         new_pats.push(new_pat);
       }
-      let pat = Pat::record(new_pats);
+      let pat = Pat::tuple(new_pats);
       Ok((val_env, Ty::Record(ty_rows), pat))
     }
     // SML Definition Appendix A - list patterns are sugar for constructors
@@ -102,11 +108,11 @@ pub fn ck(cx: &Cx, st: &mut State, pat: &Located<AstPat<StrRef>>) -> Result<(Val
         new_pats.push(new_pat);
       }
       let pat = new_pats.into_iter().rev().fold(
-        Pat::zero(Con::Ctor(StrRef::NIL, Span::Finite(2))),
+        Pat::zero(Con::Ctor(StrRef::NIL, Sym::LIST, Span::Finite(2))),
         |ac, x| {
           Pat::Con(
-            Con::Ctor(StrRef::CONS, Span::Finite(2)),
-            vec![Pat::record(vec![x, ac])],
+            Con::Ctor(StrRef::CONS, Sym::LIST, Span::Finite(2)),
+            vec![Pat::tuple(vec![x, ac])],
           )
         },
       );
@@ -125,7 +131,7 @@ pub fn ck(cx: &Cx, st: &mut State, pat: &Located<AstPat<StrRef>>) -> Result<(Val
       let (other_ve, rhs_ty, rhs_pat) = ck(cx, st, rhs)?;
       env_merge(&mut val_env, other_ve, pat.loc, Item::Val)?;
       let arg_ty = Ty::pair(lhs_ty, rhs_ty);
-      let arg_pat = Pat::record(vec![lhs_pat, rhs_pat]);
+      let arg_pat = Pat::tuple(vec![lhs_pat, rhs_pat]);
       let long = Long {
         structures: vec![],
         last: *vid,
@@ -136,7 +142,7 @@ pub fn ck(cx: &Cx, st: &mut State, pat: &Located<AstPat<StrRef>>) -> Result<(Val
     // SML Definition (42)
     AstPat::Typed(inner_pat, ty) => {
       let (val_env, pat_ty, inner_pat) = ck(cx, st, inner_pat)?;
-      let ty = ty::ck(cx, &st.tys, ty)?;
+      let ty = ty::ck(cx, st, ty)?;
       st.unify(pat.loc, ty, pat_ty.clone())?;
       Ok((val_env, pat_ty, inner_pat))
     }
@@ -152,10 +158,11 @@ pub fn ck(cx: &Cx, st: &mut State, pat: &Located<AstPat<StrRef>>) -> Result<(Val
       }
       let (mut val_env, pat_ty, inner_pat) = ck(cx, st, inner_pat)?;
       if let Some(ty) = ty {
-        let ty = ty::ck(cx, &st.tys, ty)?;
+        let ty = ty::ck(cx, st, ty)?;
         st.unify(pat.loc, ty, pat_ty.clone())?;
       }
-      let val_info = ValInfo::val(TyScheme::mono(pat_ty.clone()));
+      let mut val_info = ValInfo::val(TyScheme::mono(pat_ty.clone()));
+      val_info.def = Some(st.def(vid.loc));
       env_ins(&mut val_env, *vid, val_info, Item::Val)?;
       Ok((val_env, pat_ty, inner_pat))
     }
@@ -171,7 +178,8 @@ fn ctor(
   arg_ty: Ty,
   arg_pat: Pat,
 ) -> Result<(Ty, Pat)> {
-  let val_info = get_val_info(get_env(&cx.env, long)?, long.last)?;
+  let env = get_env(st, &cx.env, long)?;
+  let val_info = get_val_info(st, env, long.last)?;
   if val_info.id_status.is_val() {
     return Err(long.loc().wrap(Error::PatWrongIdStatus));
   }
@@ -186,7 +194,7 @@ fn ctor(
     _ => unreachable!(),
   };
   let span = get_span(&st.tys, sym);
-  let pat = Pat::Con(Con::Ctor(long.last.val, span), vec![arg_pat]);
+  let pat = Pat::Con(Con::Ctor(long.last.val, sym, span), vec![arg_pat]);
   Ok((ctor_res_ty, pat))
 }
 
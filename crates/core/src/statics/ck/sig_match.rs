@@ -24,39 +24,73 @@
 
 use crate::loc::Loc;
 use crate::statics::ck::enrich;
-use crate::statics::ck::util::get_ty_sym;
 use crate::statics::ty_rzn::TyRealization;
-use crate::statics::types::{Env, Result, Sig, State, TyEnv};
+use crate::statics::types::{Env, Error, Item, Result, Sig, State, Sym, TyEnv};
+use std::collections::HashMap;
 
-/// Returns `Ok((E, ty_rzn))` iff `sig >= E` (and `ty_rzn` is the witness) and `env >> E`.
-pub fn ck(st: &mut State, loc: Loc, env: Env, sig: &Sig) -> Result<(Env, TyRealization)> {
+/// Returns `Ok((E, ty_rzn, syms))` iff `sig >= E` (and `ty_rzn` is the witness) and `env >> E`.
+/// `syms` maps each bound type name of `sig` to the symbol in `env` whose type function it is
+/// realized as.
+pub fn ck(
+  st: &mut State,
+  loc: Loc,
+  env: Env,
+  sig: &Sig,
+) -> Result<(Env, TyRealization, HashMap<Sym, Sym>)> {
   let mut ty_rzn = TyRealization::default();
-  for &bound_ty_sym in sig.ty_names.iter() {
-    let ty_name = loc.wrap(bound_ty_sym.name());
-    let env_ty_sym = get_ty_sym(&env, ty_name)?;
-    let ty_fcn = st.tys.get(&env_ty_sym).ty_fcn.clone();
-    ty_rzn.insert_ty_fcn(bound_ty_sym, ty_fcn);
+  let mut syms = HashMap::new();
+  // realize the bound type names of `sig` as the types with the same names (and paths) in `env`.
+  let mut stack = vec![(&env, &sig.env)];
+  while let Some((got, want)) = stack.pop() {
+    for (name, want) in want.str_env.iter() {
+      match got.str_env.get(name) {
+        None => return Err(loc.wrap(Error::Undefined(Item::Struct, *name))),
+        Some(got) => stack.push((got, want)),
+      }
+    }
+    for (&name, &bound_ty_sym) in want.ty_env.inner.iter() {
+      // a bound type name may appear more than once, e.g. because of `sharing`. we realize it as
+      // the first type we find for it, and `enrich::ck` checks the rest are the same.
+      if !sig.ty_names.contains(&bound_ty_sym) || syms.contains_key(&bound_ty_sym) {
+        continue;
+      }
+      let env_ty_sym = match got.ty_env.inner.get(&name) {
+        None => return Err(loc.wrap(Error::Undefined(Item::Ty, name))),
+        Some(&sym) => sym,
+      };
+      let ty_fcn = st.tys.get(&env_ty_sym).ty_fcn.clone();
+      ty_rzn.insert_ty_fcn(bound_ty_sym, ty_fcn);
+      syms.insert(bound_ty_sym, env_ty_sym);
+    }
   }
   enrich::ck(loc, &st.tys, &ty_rzn, &env, &sig.env)?;
-  let env = Env {
+  Ok((restrict(env, &sig.env), ty_rzn, syms))
+}
+
+/// Returns `env` with only the things also in `want`.
+fn restrict(env: Env, want: &Env) -> Env {
+  Env {
     str_env: env
       .str_env
       .into_iter()
-      .filter(|(name, _)| sig.env.str_env.contains_key(name))
+      .filter_map(|(name, env)| {
+        let want = want.str_env.get(&name)?;
+        Some((name, restrict(env, want)))
+      })
       .collect(),
     ty_env: TyEnv {
       inner: env
         .ty_env
         .inner
         .into_iter()
-        .filter(|(name, _)| sig.env.ty_env.inner.contains_key(name))
+        .filter(|(name, _)| want.ty_env.inner.contains_key(name))
         .collect(),
     },
     val_env: env
       .val_env
       .into_iter()
-      .filter(|(name, _)| sig.env.val_env.contains_key(name))
+      .filter(|(name, _)| want.val_env.contains_key(name))
       .collect(),
-  };
-  Ok((env, ty_rzn))
+    def: env.def,
+  }
 }
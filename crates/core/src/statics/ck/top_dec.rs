@@ -1,64 +1,114 @@
 //! Check top-level declarations.
 
-use crate::ast::{SigExp, Spec, StrDec, StrExp, TopDec};
+use crate::ast::{SigExp, Spec, StrDec, StrExp, TopDec, Ty as AstTy};
 use crate::intern::StrRef;
-use crate::loc::Located;
-use crate::statics::ck::util::{env_ins, get_env};
+use crate::loc::{Loc, Located};
+use crate::statics::ck::util::{env_ins, get_env, get_ty_sym, insert_ty_vars};
 use crate::statics::ck::{dec, sig_match, ty};
 use crate::statics::ty_rzn::TyRealization;
 use crate::statics::types::{
-  Basis, Env, Error, FunEnv, FunSig, Item, Result, Sig, SigEnv, State, StrEnv, Ty, TyEnv, TyInfo,
-  TyScheme, ValEnv, ValInfo,
+  Basis, Env, Error, FunEnv, FunSig, Item, Result, Sig, SigEnv, State, StrEnv, Sym, Ty, TyEnv,
+  TyInfo, TyNameSet, TyScheme, TyVar, ValEnv, ValInfo,
 };
+use crate::token::TyVar as AstTyVar;
+use maplit::hashmap;
+use std::collections::{BTreeMap, HashMap};
 
-pub fn ck(bs: &mut Basis, st: &mut State, top_dec: &Located<TopDec<StrRef>>) -> Result<()> {
+/// Checks a top-level declaration in the `Basis`, returning a `Basis` containing only what it
+/// declares. Errors are recorded in the `State`.
+pub fn ck(bs: &Basis, st: &mut State, top_dec: &Located<TopDec<StrRef>>) -> Basis {
+  let mut ret = Basis::default();
   match &top_dec.val {
     // SML Definition (87)
     TopDec::StrDec(str_dec) => {
-      let env = ck_str_dec(bs, st, str_dec)?;
-      bs.env.extend(env);
+      let env = ck_str_dec(bs, st, str_dec);
+      ret.env = st.recover(env);
     }
     // SML Definition (88)
     TopDec::SigDec(sig_binds) => {
       let mut sig_env = SigEnv::new();
       // SML Definition (66), SML Definition (67)
       for sig_bind in sig_binds {
-        let env = ck_sig_exp(bs, st, &sig_bind.exp)?;
+        let env = match ck_sig_exp(bs, st, &sig_bind.exp) {
+          Ok(x) => x,
+          Err(e) => {
+            st.err(e);
+            continue;
+          }
+        };
+        let mut sig = env_to_sig(env);
+        sig.def = Some(st.def(sig_bind.id.loc));
         // allow shadowing.
-        sig_env.insert(sig_bind.id.val, env_to_sig(env));
+        sig_env.insert(sig_bind.id.val, sig);
       }
-      bs.sig_env.extend(sig_env);
+      ret.sig_env = sig_env;
     }
     // SML Definition (85), SML Definition (89)
     TopDec::FunDec(fun_binds) => {
       let mut fun_env = FunEnv::new();
       // SML Definition (86)
       for fun_bind in fun_binds {
-        let sig_env = ck_sig_exp(bs, st, &fun_bind.sig_exp)?;
+        let sig_env = match ck_sig_exp(bs, st, &fun_bind.sig_exp) {
+          Ok(x) => x,
+          Err(e) => {
+            st.err(e);
+            continue;
+          }
+        };
+        let gen_syms = st.generated_syms();
         let mut bs = bs.clone();
-        bs.env.str_env.insert(fun_bind.str_id.val, sig_env.clone());
-        let str_env = ck_str_exp(&bs, st, &fun_bind.str_exp)?;
-        // TODO might not be right? a lot of stuff going on with ty names.
+        let mut arg_env = sig_env.clone();
+        arg_env.def = Some(st.def(fun_bind.str_id.loc));
+        bs.env.str_env.insert(fun_bind.str_id.val, arg_env);
+        let str_env = match ck_str_exp(&bs, st, &fun_bind.str_exp) {
+          Ok(x) => x,
+          Err(e) => {
+            st.err(e);
+            continue;
+          }
+        };
+        // the output binds the type names generated by the body, as opposed to those from the
+        // input or the surrounding basis.
+        let mut names = Vec::new();
+        env_ty_names(&str_env, &mut names);
+        let mut ty_names = TyNameSet::new();
+        while let Some(sym) = names.pop() {
+          if gen_syms.contains_sym(sym) || !ty_names.insert(sym) {
+            continue;
+          }
+          let ty_info = st.tys.get(&sym);
+          names.extend(ty_info.ty_fcn.ty.ty_names());
+          for val_info in ty_info.val_env.values() {
+            names.extend(val_info.ty_scheme.ty.ty_names());
+          }
+        }
         let fun_sig = FunSig {
           input: env_to_sig(sig_env),
-          output: env_to_sig(str_env),
+          output: Sig {
+            ty_names,
+            env: str_env,
+            def: None,
+          },
+          def: Some(st.def(fun_bind.fun_id.loc)),
         };
         // allow shadowing.
         fun_env.insert(fun_bind.fun_id.val, fun_sig);
       }
-      bs.fun_env.extend(fun_env);
+      ret.fun_env = fun_env;
     }
   }
   st.subst.use_overloaded_defaults();
-  Ok(())
+  ret
 }
 
 /// SML Definition (65)
 fn env_to_sig(env: Env) -> Sig {
-  // TODO what about signature specs inside this sig?
+  let mut ty_names = Vec::new();
+  env_ty_syms(&env, &mut ty_names);
   Sig {
-    ty_names: env.ty_env.inner.values().copied().collect(),
+    ty_names: ty_names.into_iter().collect(),
     env,
+    def: None,
   }
 }
 
@@ -67,25 +117,25 @@ fn ck_str_exp(bs: &Basis, st: &mut State, str_exp: &Located<StrExp<StrRef>>) ->
     // SML Definition (50)
     StrExp::Struct(str_dec) => ck_str_dec(bs, st, str_dec),
     // SML Definition (51)
-    StrExp::LongStrId(long) => match get_env(&bs.env, long)?.str_env.get(&long.last.val) {
+    StrExp::LongStrId(long) => match get_env(st, &bs.env, long)?.str_env.get(&long.last.val) {
       None => {
         let err = Error::Undefined(Item::Struct, long.last.val);
         Err(long.last.loc.wrap(err))
       }
-      Some(env) => Ok(env.clone()),
+      Some(env) => {
+        st.use_def(long.last.loc, env.def);
+        Ok(env.clone())
+      }
     },
     // SML Definition (52), SML Definition (53)
     StrExp::Ascription(lhs, rhs, opaque) => {
       let env = ck_str_exp(bs, st, lhs)?;
       let mut sig = env_to_sig(ck_sig_exp(bs, st, rhs)?);
-      let (env, _) = sig_match::ck(st, lhs.loc, env, &sig)?;
+      let (env, _, _) = sig_match::ck(st, lhs.loc, env, &sig)?;
       if *opaque {
-        let mut ty_rzn = TyRealization::default();
-        for &old in sig.ty_names.iter() {
-          let new = st.new_sym(str_exp.loc.wrap(old.name()));
-          ty_rzn.insert_sym(old, new);
-        }
-        ty_rzn.get_env(&mut st.tys, &mut sig.env);
+        let ty_rzn = TyRealization::default();
+        let names = sig.ty_names.iter().copied();
+        instantiate(st, &mut sig.env, ty_rzn, HashMap::new(), names, str_exp.loc);
         Ok(sig.env)
       } else {
         Ok(env)
@@ -95,20 +145,21 @@ fn ck_str_exp(bs: &Basis, st: &mut State, str_exp: &Located<StrExp<StrRef>>) ->
     StrExp::FunctorApp(fun_id, arg) => match bs.fun_env.get(&fun_id.val) {
       None => Err(fun_id.loc.wrap(Error::Undefined(Item::Functor, fun_id.val))),
       Some(fun_sig) => {
+        st.use_def(fun_id.loc, fun_sig.def);
         let arg_env = ck_str_exp(bs, st, arg)?;
-        let (_, mut ty_rzn) = sig_match::ck(st, arg.loc, arg_env, &fun_sig.input)?;
+        // the bound type names of the input are realized as the types of the argument, and those of
+        // the output (the ones the body generates) are new for each application.
+        let (_, ty_rzn, syms) = sig_match::ck(st, arg.loc, arg_env, &fun_sig.input)?;
         let mut ret = fun_sig.output.env.clone();
-        for &old in fun_sig.output.ty_names.iter() {
-          let new = st.new_sym(str_exp.loc.wrap(old.name()));
-          ty_rzn.insert_sym(old, new);
-        }
-        ty_rzn.get_env(&mut st.tys, &mut ret);
+        let names = fun_sig.output.ty_names.iter().copied();
+        instantiate(st, &mut ret, ty_rzn, syms, names, str_exp.loc);
         Ok(ret)
       }
     },
     // SML Definition (55)
     StrExp::Let(fst, snd) => {
-      let env = ck_str_dec(bs, st, fst)?;
+      let env = ck_str_dec(bs, st, fst);
+      let env = st.recover(env);
       let mut bs = bs.clone();
       bs.env.extend(env);
       ck_str_exp(&bs, st, snd)
@@ -117,6 +168,7 @@ fn ck_str_exp(bs: &Basis, st: &mut State, str_exp: &Located<StrExp<StrRef>>) ->
 }
 
 fn ck_str_dec(bs: &Basis, st: &mut State, str_dec: &Located<StrDec<StrRef>>) -> Result<Env> {
+  st.record_scope(str_dec.loc, &bs.env, false);
   match &str_dec.val {
     // SML Definition (56)
     StrDec::Dec(dec) => dec::ck(&bs.to_cx(), st, dec),
@@ -125,7 +177,14 @@ fn ck_str_dec(bs: &Basis, st: &mut State, str_dec: &Located<StrDec<StrRef>>) ->
       let mut str_env = StrEnv::new();
       // SML Definition (61)
       for str_bind in str_binds {
-        let env = ck_str_exp(bs, st, &str_bind.exp)?;
+        let mut env = match ck_str_exp(bs, st, &str_bind.exp) {
+          Ok(x) => x,
+          Err(e) => {
+            st.err(e);
+            continue;
+          }
+        };
+        env.def = Some(st.def(str_bind.id.loc));
         // allow shadowing.
         str_env.insert(str_bind.id.val, env);
       }
@@ -133,7 +192,8 @@ fn ck_str_dec(bs: &Basis, st: &mut State, str_dec: &Located<StrDec<StrRef>>) ->
     }
     // SML Definition (58)
     StrDec::Local(fst, snd) => {
-      let env = ck_str_dec(bs, st, fst)?;
+      let env = ck_str_dec(bs, st, fst);
+      let env = st.recover(env);
       let mut bs = bs.clone();
       bs.env.extend(env);
       ck_str_dec(&bs, st, snd)
@@ -144,7 +204,13 @@ fn ck_str_dec(bs: &Basis, st: &mut State, str_dec: &Located<StrDec<StrRef>>) ->
       let mut ret = Env::default();
       for str_dec in str_decs {
         bs.env.extend(ret.clone());
-        ret.extend(ck_str_dec(&bs, st, str_dec)?);
+        let env = ck_str_dec(&bs, st, str_dec);
+        ret.extend(st.recover(env));
+        if st.is_before_scope(str_dec.loc) {
+          let mut env = bs.env.clone();
+          env.extend(ret.clone());
+          st.scope = Some((env, false));
+        }
       }
       Ok(ret)
     }
@@ -163,10 +229,266 @@ fn ck_sig_exp(bs: &Basis, st: &mut State, sig_exp: &Located<SigExp<StrRef>>) ->
       // a `signature` top dec, we add the type names of that sig to the basis. (Why do we do that?
       // I don't know). Is the whole "you may need to rename bound type names" thing made not
       // necessary by `Sym`, which is meant to be globally unique?
-      Some(sig) => Ok(sig.env.clone()),
+      Some(sig) => {
+        st.use_def(sig_id.loc, sig.def);
+        // each use of a signature gets fresh bound type names, so that e.g. two structures specified
+        // with the same signature have distinct types unless they are declared to be shared.
+        let mut env = sig.env.clone();
+        let ty_rzn = TyRealization::default();
+        let names = sig.ty_names.iter().copied();
+        instantiate(st, &mut env, ty_rzn, HashMap::new(), names, sig_id.loc);
+        Ok(env)
+      }
     },
     // SML Definition (64)
-    SigExp::Where(_, _, _, _) => Err(sig_exp.loc.wrap(Error::Todo("`where`"))),
+    SigExp::Where(inner, ty_vars, ty_con, ty) => {
+      let mut env = ck_sig_exp(bs, st, inner)?;
+      let ty_con_env = get_env(st, &env, ty_con)?;
+      let old = get_ty_sym(st, ty_con_env, ty_con.last)?;
+      let old_info = st.tys.get(&old);
+      if !is_abstract(old, old_info) {
+        return Err(
+          ty_con
+            .loc()
+            .wrap(Error::WhereTyNotAbstract(ty_con.last.val)),
+        );
+      }
+      let want_len = old_info.ty_fcn.ty_vars.len();
+      if want_len != ty_vars.len() {
+        return Err(
+          ty_con
+            .loc()
+            .wrap(Error::WrongNumTyArgs(want_len, ty_vars.len())),
+        );
+      }
+      let want_equality = old_info.equality;
+      let mut cx = bs.to_cx();
+      insert_ty_vars(&mut cx, st, ty_vars)?;
+      let new_ty = ty::ck(&cx, st, ty)?;
+      let ty_fcn = TyScheme {
+        ty_vars: ty_vars
+          .iter()
+          .map(|tv| {
+            let tv = *cx.ty_vars.get(&tv.val).unwrap();
+            st.subst.remove_bound(&tv);
+            tv
+          })
+          .collect(),
+        ty: new_ty,
+        overload: None,
+      };
+      let equality = ty_fcn.admits_equality(&st.tys);
+      if want_equality && !equality {
+        return Err(ty.loc.wrap(Error::NotEquality(ty_fcn.ty)));
+      }
+      // the realized type gets a new sym whose type function is the one given, so that it is no
+      // longer abstract.
+      let new = st.new_sym_from(old, sig_exp.loc);
+      let new_info = TyInfo {
+        ty_fcn: ty_fcn.clone(),
+        val_env: ValEnv::new(),
+        equality,
+      };
+      st.tys.insert(new, new_info);
+      let mut ty_rzn = TyRealization::default();
+      ty_rzn.insert_ty_fcn(old, ty_fcn);
+      realize(st, &mut env, ty_rzn, hashmap![old => new], sig_exp.loc);
+      Ok(env)
+    }
+  }
+}
+
+/// Returns whether the type `sym` with info `ty_info` is abstract, i.e. it was introduced by a
+/// `type` or `eqtype` spec and has not been realized since. Only such types may be realized by
+/// `where type` or `sharing`.
+fn is_abstract(sym: Sym, ty_info: &TyInfo) -> bool {
+  ty_info.val_env.is_empty() && matches!(ty_info.ty_fcn.ty, Ty::Ctor(_, s) if s == sym)
+}
+
+/// Identifies the abstract types `syms` in `env` by realizing them all as a single new type,
+/// whose type variables are `ty_vars`.
+fn share_tys(
+  st: &mut State,
+  env: &mut Env,
+  syms: &[Sym],
+  ty_vars: Vec<TyVar>,
+  equality: bool,
+  loc: Loc,
+) {
+  let new = st.new_sym_from(syms[0], loc);
+  let args = ty_vars.iter().map(|&tv| Ty::Var(tv)).collect();
+  let new_info = TyInfo {
+    ty_fcn: TyScheme {
+      ty_vars,
+      ty: Ty::Ctor(args, new),
+      overload: None,
+    },
+    val_env: ValEnv::new(),
+    equality,
+  };
+  st.tys.insert(new, new_info);
+  let mut ty_rzn = TyRealization::default();
+  let mut map = HashMap::new();
+  for &sym in syms {
+    if map.insert(sym, new).is_none() {
+      ty_rzn.insert_sym(sym, new);
+    }
+  }
+  realize(st, env, ty_rzn, map, loc);
+}
+
+/// Replaces each of `names` in `env` with a new sym, while also applying `ty_rzn` and `syms` as
+/// with `realize`. The names must not already be in `ty_rzn`.
+fn instantiate<I>(
+  st: &mut State,
+  env: &mut Env,
+  mut ty_rzn: TyRealization,
+  mut syms: HashMap<Sym, Sym>,
+  names: I,
+  loc: Loc,
+) where
+  I: IntoIterator<Item = Sym>,
+{
+  let mut new_names = Vec::new();
+  for old in names {
+    if syms.contains_key(&old) {
+      continue;
+    }
+    let new = st.new_sym_from(old, loc);
+    ty_rzn.insert_sym(old, new);
+    syms.insert(old, new);
+    new_names.push((old, new));
+  }
+  for (old, new) in new_names {
+    let mut ty_info = st.tys.get(&old).clone();
+    ty_rzn.get_ty_info(&mut ty_info);
+    st.tys.insert(new, ty_info);
+  }
+  realize(st, env, ty_rzn, syms, loc);
+}
+
+/// Applies `ty_rzn` to `env`. `syms` maps each type realized by `ty_rzn` to the new sym that
+/// replaces it in the `TyEnv`s, and the `TyInfo`s for those new syms must already be in the
+/// `State`.
+///
+/// Datatypes in `env` whose constructors mention a realized type are themselves replaced with new
+/// datatypes, since the `TyInfo` for the old datatype may be in use elsewhere. This may cascade to
+/// other datatypes mentioning those datatypes, so we iterate to a fixed point.
+fn realize(
+  st: &mut State,
+  env: &mut Env,
+  mut ty_rzn: TyRealization,
+  mut syms: HashMap<Sym, Sym>,
+  loc: Loc,
+) {
+  let mut all = Vec::new();
+  env_ty_syms(env, &mut all);
+  let mut datatypes = Vec::new();
+  let mut changed = true;
+  while changed {
+    changed = false;
+    for &sym in all.iter() {
+      if syms.contains_key(&sym) {
+        continue;
+      }
+      let mentions = st.tys.get(&sym).val_env.values().any(|val_info| {
+        let mut ty = val_info.ty_scheme.ty.clone();
+        ty_rzn.get_ty(&mut ty);
+        ty != val_info.ty_scheme.ty
+      });
+      if mentions {
+        let new = st.new_sym_from(sym, loc);
+        ty_rzn.insert_sym(sym, new);
+        syms.insert(sym, new);
+        datatypes.push((sym, new));
+        changed = true;
+      }
+    }
+  }
+  for (old, new) in datatypes {
+    let mut ty_info = st.tys.get(&old).clone();
+    ty_rzn.get_ty_info(&mut ty_info);
+    st.tys.insert(new, ty_info);
+  }
+  // we replace the syms in the `TyEnv`s ourselves, so applying the realization to the `Env` only
+  // affects the `ValEnv`s.
+  env_replace_ty_syms(env, &syms);
+  ty_rzn.get_env(&mut st.tys, env);
+}
+
+/// Pushes the syms in the `TyEnv`s of `env` and its structures onto `ret`, without duplicates.
+fn env_ty_syms(env: &Env, ret: &mut Vec<Sym>) {
+  for env in env.str_env.values() {
+    env_ty_syms(env, ret);
+  }
+  for &sym in env.ty_env.inner.values() {
+    if !ret.contains(&sym) {
+      ret.push(sym);
+    }
+  }
+}
+
+/// Pushes the type names mentioned by the `TyEnv`s and `ValEnv`s of `env` and its structures onto
+/// `ret`, possibly with duplicates.
+fn env_ty_names(env: &Env, ret: &mut Vec<Sym>) {
+  for env in env.str_env.values() {
+    env_ty_names(env, ret);
+  }
+  ret.extend(env.ty_env.inner.values().copied());
+  for val_info in env.val_env.values() {
+    ret.extend(val_info.ty_scheme.ty.ty_names());
+  }
+}
+
+/// Adds the names of the types in the `TyEnv`s of `env` and its structures to `ret`, keyed by their
+/// paths relative to `env`.
+fn env_ty_paths(env: &Env, prefix: &mut Vec<StrRef>, ret: &mut BTreeMap<Vec<StrRef>, Sym>) {
+  for (&name, env) in env.str_env.iter() {
+    prefix.push(name);
+    env_ty_paths(env, prefix, ret);
+    prefix.pop();
+  }
+  for (&name, &sym) in env.ty_env.inner.iter() {
+    let mut path = prefix.clone();
+    path.push(name);
+    ret.insert(path, sym);
+  }
+}
+
+/// Pushes the distinct type variables in `ty` onto `ret`, in order of first appearance.
+fn ast_ty_vars(ty: &Located<AstTy<StrRef>>, ret: &mut Vec<Located<AstTyVar<StrRef>>>) {
+  match &ty.val {
+    AstTy::TyVar(tv) => {
+      if ret.iter().all(|x| x.val != *tv) {
+        ret.push(ty.loc.wrap(*tv));
+      }
+    }
+    AstTy::Record(rows) => {
+      for row in rows {
+        ast_ty_vars(&row.val, ret);
+      }
+    }
+    AstTy::Tuple(tys) | AstTy::TyCon(tys, _) => {
+      for ty in tys {
+        ast_ty_vars(ty, ret);
+      }
+    }
+    AstTy::Arrow(lhs, rhs) => {
+      ast_ty_vars(lhs, ret);
+      ast_ty_vars(rhs, ret);
+    }
+  }
+}
+
+/// Replaces the syms in the `TyEnv`s of `env` and its structures according to `syms`.
+fn env_replace_ty_syms(env: &mut Env, syms: &HashMap<Sym, Sym>) {
+  for env in env.str_env.values_mut() {
+    env_replace_ty_syms(env, syms);
+  }
+  for sym in env.ty_env.inner.values_mut() {
+    if let Some(&new) = syms.get(sym) {
+      *sym = new;
+    }
   }
 }
 
@@ -174,13 +496,29 @@ fn ck_spec(bs: &Basis, st: &mut State, spec: &Located<Spec<StrRef>>) -> Result<E
   match &spec.val {
     // SML Definition (68)
     Spec::Val(val_descs) => {
-      let cx = bs.to_cx();
       let mut val_env = ValEnv::new();
       // SML Definition (79)
       for val_desc in val_descs {
-        let ty = ty::ck(&cx, &st.tys, &val_desc.ty)?;
-        // TODO generalize? closure?
-        let val_info = ValInfo::val(TyScheme::mono(ty));
+        // the type variables in a value description are implicitly bound by it.
+        let mut ty_vars = Vec::new();
+        ast_ty_vars(&val_desc.ty, &mut ty_vars);
+        let mut cx = bs.to_cx();
+        insert_ty_vars(&mut cx, st, &ty_vars)?;
+        let ty = ty::ck(&cx, st, &val_desc.ty)?;
+        let ty_scheme = TyScheme {
+          ty_vars: ty_vars
+            .iter()
+            .map(|tv| {
+              let tv = *cx.ty_vars.get(&tv.val).unwrap();
+              st.subst.remove_bound(&tv);
+              tv
+            })
+            .collect(),
+          ty,
+          overload: None,
+        };
+        let mut val_info = ValInfo::val(ty_scheme);
+        val_info.def = Some(st.def(val_desc.vid.loc));
         env_ins(&mut val_env, val_desc.vid, val_info, Item::Val)?;
       }
       Ok(val_env.into())
@@ -190,16 +528,29 @@ fn ck_spec(bs: &Basis, st: &mut State, spec: &Located<Spec<StrRef>>) -> Result<E
       let mut ty_env = TyEnv::default();
       // SML Definition (80)
       for ty_desc in ty_descs {
-        if let Some(tv) = ty_desc.ty_vars.first() {
-          return Err(tv.loc.wrap(Error::Todo("type variables in spec")));
-        }
+        let mut cx = bs.to_cx();
+        insert_ty_vars(&mut cx, st, &ty_desc.ty_vars)?;
+        let ty_vars: Vec<_> = ty_desc
+          .ty_vars
+          .iter()
+          .map(|tv| {
+            let tv = *cx.ty_vars.get(&tv.val).unwrap();
+            st.subst.remove_bound(&tv);
+            tv
+          })
+          .collect();
         let sym = st.new_sym(ty_desc.ty_con);
         // TODO equality check
         env_ins(&mut ty_env.inner, ty_desc.ty_con, sym, Item::Ty)?;
+        let args = ty_vars.iter().map(|&tv| Ty::Var(tv)).collect();
         st.tys.insert(
           sym,
           TyInfo {
-            ty_fcn: TyScheme::mono(Ty::Ctor(vec![], sym)),
+            ty_fcn: TyScheme {
+              ty_vars,
+              ty: Ty::Ctor(args, sym),
+              overload: None,
+            },
             val_env: ValEnv::new(),
             equality: *equality,
           },
@@ -210,17 +561,18 @@ fn ck_spec(bs: &Basis, st: &mut State, spec: &Located<Spec<StrRef>>) -> Result<E
     // SML Definition (71)
     Spec::Datatype(dat_binds) => dec::ck_dat_binds(bs.to_cx(), st, dat_binds),
     // SML Definition (72)
-    Spec::DatatypeCopy(ty_con, long) => dec::ck_dat_copy(&bs.to_cx(), &st.tys, *ty_con, long),
+    Spec::DatatypeCopy(ty_con, long) => dec::ck_dat_copy(&bs.to_cx(), st, *ty_con, long),
     // SML Definition (73)
     Spec::Exception(ex_descs) => {
       let cx = bs.to_cx();
       let mut val_env = ValEnv::new();
       // SML Definition (83)
       for ex_desc in ex_descs {
-        let val_info = match &ex_desc.ty {
+        let mut val_info = match &ex_desc.ty {
           None => ValInfo::exn(),
-          Some(ty) => ValInfo::exn_fn(ty::ck(&cx, &st.tys, ty)?),
+          Some(ty) => ValInfo::exn_fn(ty::ck(&cx, st, ty)?),
         };
+        val_info.def = Some(st.def(ex_desc.vid.loc));
         env_ins(&mut val_env, ex_desc.vid, val_info, Item::Val)?;
       }
       Ok(val_env.into())
@@ -230,7 +582,8 @@ fn ck_spec(bs: &Basis, st: &mut State, spec: &Located<Spec<StrRef>>) -> Result<E
       let mut str_env = StrEnv::new();
       // SML Definition (84)
       for str_desc in str_descs {
-        let env = ck_sig_exp(bs, st, &str_desc.exp)?;
+        let mut env = ck_sig_exp(bs, st, &str_desc.exp)?;
+        env.def = Some(st.def(str_desc.str_id.loc));
         // allow shadowing.
         str_env.insert(str_desc.str_id.val, env);
       }
@@ -244,12 +597,75 @@ fn ck_spec(bs: &Basis, st: &mut State, spec: &Located<Spec<StrRef>>) -> Result<E
       let mut ret = Env::default();
       for spec in specs {
         bs.env.extend(ret.clone());
-        let env = ck_spec(&bs, st, spec)?;
-        ret.maybe_extend(env, spec.loc)?;
+        let env = ck_spec(&bs, st, spec);
+        let env = st.recover(env);
+        let res = ret.maybe_extend(env, spec.loc);
+        st.recover(res);
       }
       Ok(ret)
     }
     // SML Definition (78)
-    Spec::Sharing(_, _) => Err(spec.loc.wrap(Error::Todo("`sharing`"))),
+    Spec::Sharing(inner, longs, is_ty) => {
+      let mut env = ck_spec(bs, st, inner)?;
+      // each group of types (given with the location and name by which they were referenced) is
+      // to be shared.
+      let mut groups = Vec::<Vec<(Loc, StrRef, Sym)>>::new();
+      if *is_ty {
+        let mut tys = Vec::with_capacity(longs.len());
+        for long in longs {
+          let ty_con_env = get_env(st, &env, long)?;
+          let sym = get_ty_sym(st, ty_con_env, long.last)?;
+          tys.push((long.loc(), long.last.val, sym));
+        }
+        groups.push(tys);
+      } else {
+        // structure sharing is a derived form: it shares every type with the same path in at least
+        // two of the structures.
+        let mut paths = BTreeMap::<Vec<StrRef>, Vec<(Loc, StrRef, Sym)>>::new();
+        for long in longs {
+          let str_env = match get_env(st, &env, long)?.str_env.get(&long.last.val) {
+            None => {
+              return Err(
+                long
+                  .last
+                  .loc
+                  .wrap(Error::Undefined(Item::Struct, long.last.val)),
+              )
+            }
+            Some(x) => x,
+          };
+          st.use_def(long.last.loc, str_env.def);
+          let mut ty_paths = BTreeMap::new();
+          env_ty_paths(str_env, &mut Vec::new(), &mut ty_paths);
+          for (path, sym) in ty_paths {
+            let name = *path.last().unwrap();
+            paths.entry(path).or_default().push((long.loc(), name, sym));
+          }
+        }
+        groups.extend(paths.into_values().filter(|tys| tys.len() >= 2));
+      }
+      for tys in groups {
+        let syms: Vec<_> = tys.iter().map(|&(_, _, sym)| sym).collect();
+        if syms.iter().all(|&sym| sym == syms[0]) {
+          continue;
+        }
+        let ty_vars = st.tys.get(&syms[0]).ty_fcn.ty_vars.clone();
+        let mut equality = false;
+        for (loc, name, sym) in tys {
+          let ty_info = st.tys.get(&sym);
+          if !is_abstract(sym, ty_info) {
+            return Err(loc.wrap(Error::SharingTyNotAbstract(name)));
+          }
+          let got_len = ty_info.ty_fcn.ty_vars.len();
+          if got_len != ty_vars.len() {
+            return Err(loc.wrap(Error::WrongNumTyArgs(ty_vars.len(), got_len)));
+          }
+          // the shared type admits equality if any of the types it identifies did.
+          equality |= ty_info.equality;
+        }
+        share_tys(st, &mut env, &syms, ty_vars, equality, spec.loc);
+      }
+      Ok(env)
+    }
   }
 }
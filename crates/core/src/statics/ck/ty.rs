@@ -4,10 +4,10 @@ use crate::ast::{Label, Ty as AstTy};
 use crate::intern::StrRef;
 use crate::loc::Located;
 use crate::statics::ck::util::{get_env, get_ty_sym};
-use crate::statics::types::{Cx, Error, Item, Result, Ty, Tys};
+use crate::statics::types::{Cx, Error, Item, Result, State, Ty};
 use std::collections::BTreeMap;
 
-pub fn ck(cx: &Cx, tys: &Tys, ty: &Located<AstTy<StrRef>>) -> Result<Ty> {
+pub fn ck(cx: &Cx, st: &mut State, ty: &Located<AstTy<StrRef>>) -> Result<Ty> {
   // SML Definition (48) is handled by the parser
   match &ty.val {
     // SML Definition (44)
@@ -20,7 +20,7 @@ pub fn ck(cx: &Cx, tys: &Tys, ty: &Located<AstTy<StrRef>>) -> Result<Ty> {
       let mut ty_rows = BTreeMap::new();
       // SML Definition (49)
       for row in rows {
-        let ty = ck(cx, tys, &row.val)?;
+        let ty = ck(cx, st, &row.val)?;
         if ty_rows.insert(row.lab.val, ty).is_some() {
           return Err(row.lab.loc.wrap(Error::DuplicateLabel(row.lab.val)));
         }
@@ -31,30 +31,30 @@ pub fn ck(cx: &Cx, tys: &Tys, ty: &Located<AstTy<StrRef>>) -> Result<Ty> {
     AstTy::Tuple(ts) => {
       let mut ty_rows = BTreeMap::new();
       for (idx, ty) in ts.iter().enumerate() {
-        let ty = ck(cx, tys, ty)?;
+        let ty = ck(cx, st, ty)?;
         assert!(ty_rows.insert(Label::tuple(idx), ty).is_none());
       }
       Ok(Ty::Record(ty_rows))
     }
     // SML Definition (46)
     AstTy::TyCon(args, name) => {
-      let env = get_env(&cx.env, name)?;
-      let sym = get_ty_sym(env, name.last)?;
-      let ty_fcn = &tys.get(&sym).ty_fcn;
+      let env = get_env(st, &cx.env, name)?;
+      let sym = get_ty_sym(st, env, name.last)?;
+      let ty_fcn = st.tys.get(&sym).ty_fcn.clone();
       if ty_fcn.ty_vars.len() != args.len() {
         let err = Error::WrongNumTyArgs(ty_fcn.ty_vars.len(), args.len());
         return Err(ty.loc.wrap(err));
       }
       let mut new_args = Vec::with_capacity(ty_fcn.ty_vars.len());
       for ty in args {
-        new_args.push(ck(cx, tys, ty)?);
+        new_args.push(ck(cx, st, ty)?);
       }
       Ok(ty_fcn.apply_args(new_args))
     }
     // SML Definition (47)
     AstTy::Arrow(arg, res) => {
-      let arg = ck(cx, tys, arg)?;
-      let res = ck(cx, tys, res)?;
+      let arg = ck(cx, st, arg)?;
+      let res = ck(cx, st, res)?;
       Ok(Ty::Arrow(arg.into(), res.into()))
     }
   }
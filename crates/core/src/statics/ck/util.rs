@@ -86,30 +86,43 @@ pub fn generalize(
 }
 
 /// Returns `Ok(e)` iff `env` contains the environment `e` after traversing the `StrEnv`s of `env`
-/// as directed by `long.structures`.
-pub fn get_env<'env>(mut env: &'env Env, long: &Long<StrRef>) -> Result<&'env Env> {
+/// as directed by `long.structures`. Records the uses of the structures in the `State`.
+pub fn get_env<'env>(st: &mut State, mut env: &'env Env, long: &Long<StrRef>) -> Result<&'env Env> {
   for &s in long.structures.iter() {
     env = match env.str_env.get(&s.val) {
       None => return Err(s.loc.wrap(Error::Undefined(Item::Struct, s.val))),
       Some(x) => x,
-    }
+    };
+    st.use_def(s.loc, env.def);
   }
   Ok(env)
 }
 
-/// Returns `Ok(vi)` iff the `ValEnv` of `env` maps `name` to `vi`.
-pub fn get_val_info(env: &Env, name: Located<StrRef>) -> Result<&ValInfo> {
+/// Returns `Ok(vi)` iff the `ValEnv` of `env` maps `name` to `vi`. Records the use of `name` in the
+/// `State`.
+pub fn get_val_info<'env>(
+  st: &mut State,
+  env: &'env Env,
+  name: Located<StrRef>,
+) -> Result<&'env ValInfo> {
   match env.val_env.get(&name.val) {
     None => Err(name.loc.wrap(Error::Undefined(Item::Val, name.val))),
-    Some(val_info) => Ok(val_info),
+    Some(val_info) => {
+      st.use_def(name.loc, val_info.def);
+      Ok(val_info)
+    }
   }
 }
 
-/// Returns `Ok(sym)` iff the `TyEnv` of `env` maps `name` to `sym`.
-pub fn get_ty_sym(env: &Env, name: Located<StrRef>) -> Result<Sym> {
+/// Returns `Ok(sym)` iff the `TyEnv` of `env` maps `name` to `sym`. Records the use of `name` in the
+/// `State`.
+pub fn get_ty_sym(st: &mut State, env: &Env, name: Located<StrRef>) -> Result<Sym> {
   match env.ty_env.inner.get(&name.val) {
     None => Err(name.loc.wrap(Error::Undefined(Item::Ty, name.val))),
-    Some(sym) => Ok(*sym),
+    Some(&sym) => {
+      st.use_def(name.loc, st.sym_def(sym));
+      Ok(sym)
+    }
   }
 }
 
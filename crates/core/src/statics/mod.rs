@@ -10,13 +10,17 @@ mod ty_rzn;
 mod types;
 
 use crate::ast::TopDec;
-use crate::intern::StrRef;
-use crate::loc::Located;
-use crate::statics::types::{Basis, Result, State};
+use crate::intern::{StrRef, StrStore};
+use crate::loc::{Loc, Located};
+use crate::statics::types::State;
+
+pub use crate::statics::types::{Def, Error, Lint, Severity};
 
 /// The data computed when running static analysis.
 pub struct Statics {
-  bs: Basis,
+  bs: types::Basis,
+  /// The bases returned by `get_in`.
+  declared: Vec<types::Basis>,
   st: State,
 }
 
@@ -25,13 +29,193 @@ impl Statics {
   /// Returns the initial information to begin running the statics.
   pub fn new() -> Self {
     let (bs, st) = std_lib::get();
-    Self { bs, st }
+    Self {
+      bs,
+      declared: Vec::new(),
+      st,
+    }
+  }
+
+  /// Performs static analysis on a top-level declaration from the file with index `file`. Returns
+  /// the errors found, in source order. The returned `Vec` is empty iff everything typechecks.
+  pub fn get(&mut self, file: usize, top_dec: &Located<TopDec<StrRef>>) -> Vec<Located<Error>> {
+    self.st.file = file;
+    self.st.record_scope(top_dec.loc, &self.bs.env, false);
+    let bs = ck::ck_top_dec(&self.bs, &mut self.st, top_dec);
+    self.bs.extend(bs);
+    if self.st.is_before_scope(top_dec.loc) {
+      self.st.scope = Some((self.bs.env.clone(), false));
+    }
+    self.st.take_errors()
+  }
+
+  /// Returns the current basis. Before anything is checked with `get`, this contains only the
+  /// standard library.
+  pub fn basis(&self) -> Basis {
+    Basis(self.bs.clone())
+  }
+
+  /// Like `get`, but checks a sequence of top-level declarations, like those in a file, in the given
+  /// basis instead of the current basis, which is left unchanged. Also returns a basis containing
+  /// only what the declarations declare.
+  pub fn get_in(
+    &mut self,
+    bs: &Basis,
+    file: usize,
+    top_decs: &[Located<TopDec<StrRef>>],
+  ) -> (Basis, Vec<Located<Error>>) {
+    self.st.file = file;
+    let mut bs = bs.0.clone();
+    let mut ret = types::Basis::default();
+    let mut errors = Vec::new();
+    for top_dec in top_decs {
+      self.st.record_scope(top_dec.loc, &bs.env, false);
+      let new = ck::ck_top_dec(&bs, &mut self.st, top_dec);
+      bs.extend(new.clone());
+      ret.extend(new);
+      if self.st.is_before_scope(top_dec.loc) {
+        self.st.scope = Some((bs.env.clone(), false));
+      }
+      errors.append(&mut self.st.take_errors());
+    }
+    self.declared.push(ret.clone());
+    (Basis(ret), errors)
+  }
+
+  /// Returns where the name used at the byte index `idx` in the file with index `file` was defined,
+  /// if there is such a name and it was defined in user code.
+  pub fn definition(&self, file: usize, idx: usize) -> Option<Def> {
+    self
+      .st
+      .uses
+      .iter()
+      .find(|&&(f, loc, _)| f == file && loc.contains(idx))
+      .map(|&(_, _, def)| def)
   }
 
-  /// Performs static analysis on a top-level declaration. Returns `Ok(())` iff everything
-  /// typechecks.
-  pub fn get(&mut self, top_dec: &Located<TopDec<StrRef>>) -> Result<()> {
-    ck::ck_top_dec(&mut self.bs, &mut self.st, top_dec)
+  /// Makes the statics record what is in scope at the byte index `idx` in the file with index
+  /// `file`, for use by `names_in_scope`. Call this before checking that file.
+  pub fn set_scope_at(&mut self, file: usize, idx: usize) {
+    self.st.scope_at = Some((file, idx));
+  }
+
+  /// Returns the names in scope at the place given to `set_scope_at`, and whether that place is
+  /// inside an expression. If `path` is not empty, the names are instead those in the structure
+  /// named by `path` in scope at that place, and this returns `None` if there is no such structure.
+  pub fn names_in_scope(&self, store: &StrStore, path: &[&str]) -> Option<(Vec<Name>, bool)> {
+    let (mut env, in_exp) = match &self.st.scope {
+      Some((env, in_exp)) => (env, *in_exp),
+      None => (&self.bs.env, false),
+    };
+    // like `get_env`, but this can't record uses.
+    for &s in path {
+      env = env.str_env.get(&store.lookup(s)?)?;
+    }
+    // the argument of a functor declared with the derived form has no name the user could write.
+    let str_env = env.str_env.keys().filter(|&&x| x != StrRef::FUNCTOR_ARG);
+    let str_env = str_env.map(|&x| Name {
+      name: store.get(x).to_owned(),
+      kind: NameKind::Structure,
+      detail: None,
+    });
+    let ty_env = env.ty_env.inner.keys().map(|&x| Name {
+      name: store.get(x).to_owned(),
+      kind: NameKind::Type,
+      detail: None,
+    });
+    let val_env = env.val_env.iter().map(|(&x, val_info)| {
+      let mut ty = val_info.ty_scheme.ty.clone();
+      ty.apply(&self.st.subst);
+      Name {
+        name: store.get(x).to_owned(),
+        kind: match val_info.id_status {
+          types::IdStatus::Ctor => NameKind::Constructor,
+          types::IdStatus::Exn => NameKind::Exception,
+          types::IdStatus::Val => NameKind::Value,
+        },
+        detail: Some(types::show_ty(store, &ty)),
+      }
+    });
+    Some((str_env.chain(ty_env).chain(val_env).collect(), in_exp))
+  }
+
+  /// Returns where the name at the byte index `idx` in the file with index `file` was defined, and
+  /// the file and location of every use of it, if there is such a name and it was defined in user
+  /// code. The name may be either at its definition or at one of its uses.
+  pub fn references(&self, file: usize, idx: usize) -> Option<(Def, Vec<(usize, Loc)>)> {
+    let def = self.definition(file, idx).or_else(|| {
+      self
+        .st
+        .defs
+        .iter()
+        .copied()
+        .find(|def| def.file == file && def.loc.contains(idx))
+    })?;
+    let mut uses: Vec<_> = self
+      .st
+      .uses
+      .iter()
+      .filter(|&&(_, _, d)| d == def)
+      .map(|&(f, loc, _)| (f, loc))
+      .collect();
+    uses.sort_unstable();
+    uses.dedup();
+    Some((def, uses))
+  }
+
+  /// Returns every use of a name defined in user code checked so far, with the file and location of
+  /// the use and where the name was defined.
+  pub fn uses(&self) -> &[(usize, Loc, Def)] {
+    &self.st.uses
+  }
+
+  /// Returns whether the name defined at `def` is in the same environment as a different name shown
+  /// as `name`, in the same namespace. If so, renaming the former to `name` would make one shadow
+  /// the other.
+  pub fn collides(&self, store: &StrStore, def: Def, name: &str) -> bool {
+    std::iter::once(&self.bs)
+      .chain(self.declared.iter())
+      .any(|bs| bs.collides(store, &self.st, def, name))
+  }
+
+  /// Returns the type of the smallest expression or pattern containing the byte index `idx` in the
+  /// file with index `file`, if there is one, along with its location. The type is shown with
+  /// everything learned about it from checking so far.
+  pub fn ty_at(&self, store: &StrStore, file: usize, idx: usize) -> Option<(Loc, String)> {
+    let (_, loc, ty) = self
+      .st
+      .tys_at
+      .iter()
+      .filter(|&&(f, loc, _)| f == file && loc.contains(idx))
+      .min_by_key(|&&(_, loc, _)| std::ops::Range::<usize>::from(loc).len())?;
+    let mut ty = ty.clone();
+    ty.apply(&self.st.subst);
+    Some((*loc, types::show_ty(store, &ty)))
+  }
+
+  /// Returns the arms missing from the smallest non-exhaustive match containing the byte index `idx`
+  /// in the file with index `file`, if there is one.
+  pub fn missing_arms(&self, store: &StrStore, file: usize, idx: usize) -> Option<MissingArms> {
+    let (_, arms) = self
+      .st
+      .missing_arms
+      .iter()
+      .filter(|(f, arms)| *f == file && arms.loc.contains(idx))
+      .min_by_key(|(_, arms)| std::ops::Range::<usize>::from(arms.loc).len())?;
+    let pats = arms
+      .pats
+      .iter()
+      .map(|pat| match arms.fun {
+        None => types::show_pat(store, pat),
+        Some((name, arity)) => types::show_fun_pat(store, name, arity, pat),
+      })
+      .collect();
+    Some(MissingArms {
+      loc: arms.loc,
+      last_arm: arms.last_arm,
+      is_fun: arms.fun.is_some(),
+      pats,
+    })
   }
 
   /// Finish running the statics.
@@ -40,3 +224,82 @@ impl Statics {
     assert!(self.bs.free_ty_vars(&self.st.tys).is_empty());
   }
 }
+
+/// A basis, which maps names to the structures, signatures, functors, types, and values they
+/// denote. Most checking just uses the one basis in the `Statics`, but projects with explicit
+/// scoping, like MLB files, need to keep track of many.
+#[derive(Clone, Default)]
+pub struct Basis(types::Basis);
+
+impl Basis {
+  /// Extends this basis with another. `other` overwrites `self`.
+  pub fn extend(&mut self, other: Self) {
+    self.0.extend(other.0);
+  }
+
+  /// Returns a basis in which just `new` is bound, in the namespace `ns`, to what `old` is bound to
+  /// in this. Returns `None` if `old` is not bound in this.
+  pub fn rebind(&self, ns: Namespace, old: StrRef, new: StrRef) -> Option<Self> {
+    let mut ret = types::Basis::default();
+    match ns {
+      Namespace::Structure => {
+        let env = self.0.env.str_env.get(&old)?.clone();
+        ret.env.str_env.insert(new, env);
+      }
+      Namespace::Signature => {
+        let sig = self.0.sig_env.get(&old)?.clone();
+        ret.sig_env.insert(new, sig);
+      }
+      Namespace::Functor => {
+        let fun_sig = self.0.fun_env.get(&old)?.clone();
+        ret.fun_env.insert(new, fun_sig);
+      }
+    }
+    Some(Self(ret))
+  }
+}
+
+/// A name in scope.
+#[derive(Debug)]
+pub struct Name {
+  /// The name.
+  pub name: String,
+  /// What kind of thing it names.
+  pub kind: NameKind,
+  /// Details about it, like its type.
+  pub detail: Option<String>,
+}
+
+/// The arms missing from a non-exhaustive `case`, `fn`, or `fun`.
+#[derive(Debug)]
+pub struct MissingArms {
+  /// The location of the match.
+  pub loc: Loc,
+  /// The location of the start of the last arm.
+  pub last_arm: Loc,
+  /// Whether the match is a `fun`, whose arms are like `f pat = exp`, rather than a `case` or `fn`,
+  /// whose arms are like `pat => exp`.
+  pub is_fun: bool,
+  /// The left-hand sides of the missing arms, like `f pat` or `pat`.
+  pub pats: Vec<String>,
+}
+
+/// A kind of thing a name may name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum NameKind {
+  Structure,
+  Type,
+  Constructor,
+  Exception,
+  Value,
+}
+
+/// A namespace of names which may be bound in a `Basis`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Namespace {
+  Structure,
+  Signature,
+  Functor,
+}
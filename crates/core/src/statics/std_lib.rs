@@ -209,6 +209,7 @@ pub fn get() -> (Basis, State) {
           StrRef::GT_EQ => overloaded_cmp(&mut st),
         ])
         .collect(),
+      def: None,
     },
   };
   // sanity check
@@ -5,7 +5,7 @@
 //! implemented as a `Sym`). This is useful when generating new type names as a result of signature
 //! ascription or functor application.
 
-use crate::statics::types::{Env, Sym, Ty, TyFcn, Tys, ValEnv};
+use crate::statics::types::{Env, Sym, Ty, TyFcn, TyInfo, Tys, ValEnv};
 use std::collections::HashMap;
 
 /// A mapping from symbols to type functions.
@@ -43,8 +43,7 @@ impl TyRealization {
         Some(Out::TyFcn(..)) => unreachable!(),
         Some(&Out::Sym(new)) => {
           let mut ty_info = tys.get(old).clone();
-          self.get_ty(&mut ty_info.ty_fcn.ty);
-          self.get_val_env(&mut ty_info.val_env);
+          self.get_ty_info(&mut ty_info);
           tys.insert(new, ty_info);
           *old = new;
         }
@@ -53,6 +52,12 @@ impl TyRealization {
     self.get_val_env(&mut env.val_env);
   }
 
+  /// Applies this to a `TyInfo`.
+  pub fn get_ty_info(&self, ty_info: &mut TyInfo) {
+    self.get_ty(&mut ty_info.ty_fcn.ty);
+    self.get_val_env(&mut ty_info.val_env);
+  }
+
   fn get_val_env(&self, val_env: &mut ValEnv) {
     for val_info in val_env.values_mut() {
       self.get_ty(&mut val_info.ty_scheme.ty);
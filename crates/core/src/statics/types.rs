@@ -32,8 +32,8 @@ pub enum Error {
   NonVarInAs(StrRef),
   ForbiddenBinding(StrRef),
   TyNameEscape,
-  NonExhaustiveMatch,
-  NonExhaustiveBinding,
+  NonExhaustiveMatch(Vec<Pat>),
+  NonExhaustiveBinding(Pat),
   UnreachablePattern,
   FunDecNameMismatch(StrRef, StrRef),
   FunDecWrongNumPats(usize, usize),
@@ -44,6 +44,9 @@ pub enum Error {
   NotArrowTy(Ty),
   IdStatusMismatch(IdStatus, IdStatus),
   ValEnvMismatch(Vec<StrRef>, Vec<StrRef>),
+  WhereTyNotAbstract(StrRef),
+  SharingTyNotAbstract(StrRef),
+  NotGeneral(StrRef, Ty, Ty),
   Todo(&'static str),
 }
 
@@ -65,11 +68,17 @@ impl Error {
       Self::OverloadTyMismatch(want, got) => {
         let mut ret = "mismatched types: expected one of ".to_owned();
         for &sym in want {
-          show_ty_impl(&mut ret, store, &Ty::base(sym), TyPrec::Arrow);
+          show_ty_impl(
+            &mut ret,
+            store,
+            &HashMap::new(),
+            &Ty::base(sym),
+            TyPrec::Arrow,
+          );
           ret.push_str(", ");
         }
         ret.push_str("found ");
-        show_ty_impl(&mut ret, store, got, TyPrec::Arrow);
+        show_ty_impl(&mut ret, store, &HashMap::new(), got, TyPrec::Arrow);
         ret
       }
       Self::PatWrongIdStatus => {
@@ -89,8 +98,20 @@ impl Error {
       ),
       Self::ForbiddenBinding(id) => format!("forbidden identifier in binding: {}", store.get(*id)),
       Self::TyNameEscape => "expression causes a type name to escape its scope".to_owned(),
-      Self::NonExhaustiveMatch => "non-exhaustive match".to_owned(),
-      Self::NonExhaustiveBinding => "non-exhaustive binding".to_owned(),
+      Self::NonExhaustiveMatch(pats) => {
+        let mut shown: Vec<_> = pats
+          .iter()
+          .take(MAX_SHOWN_PATS)
+          .map(|pat| show_pat(store, pat))
+          .collect();
+        if pats.len() > MAX_SHOWN_PATS {
+          shown.push("...".to_owned());
+        }
+        format!("non-exhaustive match: missing {}", shown.join(", "))
+      }
+      Self::NonExhaustiveBinding(pat) => {
+        format!("non-exhaustive binding: missing {}", show_pat(store, pat))
+      }
       Self::UnreachablePattern => "unreachable pattern".to_owned(),
       Self::FunDecNameMismatch(want, got) => format!(
         "mismatched names: expected {}, found {}",
@@ -112,7 +133,10 @@ impl Error {
       Self::DatatypeCopyNotDatatype => {
         "right-hand side of datatype copy is not a datatype".to_owned()
       }
-      Self::NotEquality(ty) => format!("not an equality type: {}", show_ty(store, ty)),
+      Self::NotEquality(ty) => format!(
+        "not an equality type: {}",
+        show_tys_named(store, &[ty]).swap_remove(0)
+      ),
       Self::NotArrowTy(ty) => format!("not a function type: {}", show_ty(store, ty)),
       Self::IdStatusMismatch(want, got) => format!(
         "mismatched identifier statuses: expected {}, found {}",
@@ -127,11 +151,68 @@ impl Error {
           want, got
         )
       }
+      Self::WhereTyNotAbstract(id) => format!(
+        "cannot realize non-abstract type with `where type`: {}",
+        store.get(*id)
+      ),
+      Self::SharingTyNotAbstract(id) => {
+        format!("cannot share non-abstract type: {}", store.get(*id))
+      }
+      Self::NotGeneral(id, want, got) => {
+        let tys = show_tys_named(store, &[want, got]);
+        format!(
+          "value not as general as its specification: {}: expected {}, found {}",
+          store.get(*id),
+          tys[0],
+          tys[1]
+        )
+      }
       Self::Todo(msg) => format!("unsupported language construct: {}", msg),
     }
   }
+
+  /// Returns the lint this error is, if it is one.
+  pub fn lint(&self) -> Option<Lint> {
+    match self {
+      Self::NonExhaustiveMatch(_) => Some(Lint::NonExhaustiveMatch),
+      Self::NonExhaustiveBinding(_) => Some(Lint::NonExhaustiveBinding),
+      Self::UnreachablePattern => Some(Lint::UnreachablePattern),
+      _ => None,
+    }
+  }
+
+  /// Returns how severe this error is. Lints are warnings, and everything else is an error.
+  pub fn severity(&self) -> Severity {
+    match self.lint() {
+      Some(_) => Severity::Warning,
+      None => Severity::Error,
+    }
+  }
+}
+
+/// How severe an error is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+  /// The program is invalid.
+  Error,
+  /// The program is valid, but likely has a mistake.
+  Warning,
+}
+
+/// A lint, a kind of error about a program that is not wrong, but is likely not what was intended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+  /// A `case`, `fn`, or `fun` does not match every value.
+  NonExhaustiveMatch,
+  /// A `val` binding does not match every value.
+  NonExhaustiveBinding,
+  /// A pattern can never match, since the patterns before it match everything it does.
+  UnreachablePattern,
 }
 
+/// The most missing patterns to show in the message for a non-exhaustive match.
+const MAX_SHOWN_PATS: usize = 3;
+
 /// Show a label.
 fn show_lab(store: &StrStore, lab: Label) -> String {
   match lab {
@@ -141,18 +222,49 @@ fn show_lab(store: &StrStore, lab: Label) -> String {
 }
 
 /// Show a type.
-fn show_ty(store: &StrStore, ty: &Ty) -> String {
+pub fn show_ty(store: &StrStore, ty: &Ty) -> String {
   let mut buf = String::new();
-  show_ty_impl(&mut buf, store, ty, TyPrec::Arrow);
+  show_ty_impl(&mut buf, store, &HashMap::new(), ty, TyPrec::Arrow);
   buf
 }
 
+/// Names for type variables, for showing types.
+type TyVarNames = HashMap<TyVar, String>;
+
+/// Show some types, naming the type variables in them `'a`, `'b`, etc. A type variable that appears
+/// in more than one of the types gets the same name in each.
+fn show_tys_named(store: &StrStore, tys: &[&Ty]) -> Vec<String> {
+  let mut names = TyVarNames::new();
+  for tv in tys.iter().flat_map(|ty| ty.free_ty_vars()) {
+    let idx = names.len();
+    names.entry(tv).or_insert_with(|| {
+      let quotes = if tv.equality { "''" } else { "'" };
+      let letter = (b'a' + (idx % 26) as u8) as char;
+      match idx / 26 {
+        0 => format!("{}{}", quotes, letter),
+        n => format!("{}{}{}", quotes, letter, n),
+      }
+    });
+  }
+  tys
+    .iter()
+    .map(|ty| {
+      let mut buf = String::new();
+      show_ty_impl(&mut buf, store, &names, ty, TyPrec::Arrow);
+      buf
+    })
+    .collect()
+}
+
 /// The impl of `show_ty`. This has a `TyPrec` argument to correctly show types with minimal amounts
 /// of parentheses while still being correct. It also mutates the input `buf` instead of returning a
 /// new `String`.
-fn show_ty_impl(buf: &mut String, store: &StrStore, ty: &Ty, prec: TyPrec) {
+fn show_ty_impl(buf: &mut String, store: &StrStore, names: &TyVarNames, ty: &Ty, prec: TyPrec) {
   match ty {
-    Ty::Var(tv) => buf.push_str(&format!("{:?}", tv)),
+    Ty::Var(tv) => match names.get(tv) {
+      None => buf.push_str(&format!("{:?}", tv)),
+      Some(name) => buf.push_str(name),
+    },
     Ty::Record(rows) => {
       if rows.is_empty() {
         buf.push_str("unit");
@@ -169,10 +281,10 @@ fn show_ty_impl(buf: &mut String, store: &StrStore, ty: &Ty, prec: TyPrec) {
         }
         let mut tys = rows.values();
         let ty = tys.next().unwrap();
-        show_ty_impl(buf, store, ty, TyPrec::App);
+        show_ty_impl(buf, store, names, ty, TyPrec::App);
         for ty in tys {
           buf.push_str(" * ");
-          show_ty_impl(buf, store, ty, TyPrec::App);
+          show_ty_impl(buf, store, names, ty, TyPrec::App);
         }
         if prec > TyPrec::Star {
           buf.push_str(")");
@@ -181,10 +293,10 @@ fn show_ty_impl(buf: &mut String, store: &StrStore, ty: &Ty, prec: TyPrec) {
         buf.push_str("{ ");
         let mut rows = rows.iter();
         let (lab, ty) = rows.next().unwrap();
-        show_row(buf, store, *lab, ty);
+        show_row(buf, store, names, *lab, ty);
         for (lab, ty) in rows {
           buf.push_str(", ");
-          show_row(buf, store, *lab, ty);
+          show_row(buf, store, names, *lab, ty);
         }
         buf.push_str(" }");
       }
@@ -193,9 +305,9 @@ fn show_ty_impl(buf: &mut String, store: &StrStore, ty: &Ty, prec: TyPrec) {
       if prec > TyPrec::Arrow {
         buf.push_str("(");
       }
-      show_ty_impl(buf, store, lhs, TyPrec::Star);
+      show_ty_impl(buf, store, names, lhs, TyPrec::Star);
       buf.push_str(" -> ");
-      show_ty_impl(buf, store, rhs, TyPrec::Arrow);
+      show_ty_impl(buf, store, names, rhs, TyPrec::Arrow);
       if prec > TyPrec::Arrow {
         buf.push_str(")");
       }
@@ -204,13 +316,13 @@ fn show_ty_impl(buf: &mut String, store: &StrStore, ty: &Ty, prec: TyPrec) {
       let mut args_iter = args.iter();
       if let Some(arg) = args_iter.next() {
         if args.len() == 1 {
-          show_ty_impl(buf, store, arg, TyPrec::App);
+          show_ty_impl(buf, store, names, arg, TyPrec::App);
         } else {
           buf.push_str("(");
-          show_ty_impl(buf, store, arg, TyPrec::Arrow);
+          show_ty_impl(buf, store, names, arg, TyPrec::Arrow);
           for arg in args_iter {
             buf.push_str(", ");
-            show_ty_impl(buf, store, arg, TyPrec::Arrow);
+            show_ty_impl(buf, store, names, arg, TyPrec::Arrow);
           }
           buf.push_str(")");
         }
@@ -221,11 +333,153 @@ fn show_ty_impl(buf: &mut String, store: &StrStore, ty: &Ty, prec: TyPrec) {
   }
 }
 
+/// Precedence of pattern operations, for `show_pat`.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum PatPrec {
+  /// Infix constructor precedence, the lowest.
+  Infix,
+  /// Application precedence.
+  App,
+  /// Atomic patterns, the highest.
+  Atomic,
+}
+
+/// Show a pattern.
+pub fn show_pat(store: &StrStore, pat: &Pat) -> String {
+  let mut buf = String::new();
+  show_pat_impl(&mut buf, store, pat, PatPrec::Infix);
+  buf
+}
+
+/// The impl of `show_pat`. Like `show_ty_impl`, this has a precedence argument to show patterns with
+/// minimal amounts of parentheses.
+fn show_pat_impl(buf: &mut String, store: &StrStore, pat: &Pat, prec: PatPrec) {
+  let (con, args) = match pat {
+    Pat::Anything => {
+      buf.push('_');
+      return;
+    }
+    Pat::Con(con, args) => (con, args),
+  };
+  match con {
+    Con::Int(n) => {
+      if *n < 0 {
+        buf.push('~');
+      }
+      buf.push_str(&n.unsigned_abs().to_string());
+    }
+    Con::Word(n) => buf.push_str(&format!("0w{}", n)),
+    Con::String(s) => {
+      buf.push('"');
+      for &b in store.get(*s).as_bytes() {
+        show_char(buf, b);
+      }
+      buf.push('"');
+    }
+    Con::Char(c) => {
+      buf.push_str("#\"");
+      show_char(buf, *c);
+      buf.push('"');
+    }
+    Con::Record(labs) => {
+      if labs.is_empty() {
+        buf.push_str("()");
+        return;
+      }
+      let is_tuple = labs.len() >= 2
+        && labs
+          .iter()
+          .enumerate()
+          .all(|(idx, lab)| Label::tuple(idx) == *lab);
+      if is_tuple {
+        buf.push('(');
+        let mut args = args.iter();
+        show_pat_impl(buf, store, args.next().unwrap(), PatPrec::Infix);
+        for arg in args {
+          buf.push_str(", ");
+          show_pat_impl(buf, store, arg, PatPrec::Infix);
+        }
+        buf.push(')');
+      } else {
+        buf.push_str("{ ");
+        let mut rows = labs.iter().zip(args.iter());
+        let (lab, arg) = rows.next().unwrap();
+        show_pat_row(buf, store, *lab, arg);
+        for (lab, arg) in rows {
+          buf.push_str(", ");
+          show_pat_row(buf, store, *lab, arg);
+        }
+        buf.push_str(" }");
+      }
+    }
+    Con::Ctor(name, _, _) => match args.first() {
+      None => buf.push_str(store.get(*name)),
+      Some(Pat::Con(Con::Record(labs), operands)) if *name == StrRef::CONS && labs.len() == 2 => {
+        if prec > PatPrec::Infix {
+          buf.push('(');
+        }
+        show_pat_impl(buf, store, &operands[0], PatPrec::App);
+        buf.push_str(" :: ");
+        show_pat_impl(buf, store, &operands[1], PatPrec::Infix);
+        if prec > PatPrec::Infix {
+          buf.push(')');
+        }
+      }
+      Some(arg) => {
+        if prec > PatPrec::App {
+          buf.push('(');
+        }
+        buf.push_str(store.get(*name));
+        buf.push(' ');
+        show_pat_impl(buf, store, arg, PatPrec::Atomic);
+        if prec > PatPrec::App {
+          buf.push(')');
+        }
+      }
+    },
+  }
+}
+
+/// Show the arguments of a clause of a `fun` named `name` with `arity` arguments, preceded by the
+/// name. If there are many arguments, `pat` is the tuple of them, as in `Pat::tuple`.
+pub fn show_fun_pat(store: &StrStore, name: StrRef, arity: usize, pat: &Pat) -> String {
+  let mut buf = store.get(name).to_owned();
+  let args = match pat {
+    Pat::Con(Con::Record(_), args) if arity > 1 => args.iter().collect(),
+    Pat::Anything if arity > 1 => vec![pat; arity],
+    _ => vec![pat],
+  };
+  for arg in args {
+    buf.push(' ');
+    show_pat_impl(&mut buf, store, arg, PatPrec::Atomic);
+  }
+  buf
+}
+
+/// Show a row of a record pattern.
+fn show_pat_row(buf: &mut String, store: &StrStore, lab: Label, pat: &Pat) {
+  buf.push_str(&show_lab(store, lab));
+  buf.push_str(" = ");
+  show_pat_impl(buf, store, pat, PatPrec::Infix);
+}
+
+/// Show a character as it would be written in a string or character constant, without the quotes.
+fn show_char(buf: &mut String, c: u8) {
+  match c {
+    b'"' => buf.push_str("\\\""),
+    b'\\' => buf.push_str("\\\\"),
+    b'\n' => buf.push_str("\\n"),
+    b'\t' => buf.push_str("\\t"),
+    b' '..=b'~' => buf.push(c as char),
+    _ => buf.push_str(&format!("\\{:03}", c)),
+  }
+}
+
 /// Show a row.
-fn show_row(buf: &mut String, store: &StrStore, lab: Label, ty: &Ty) {
+fn show_row(buf: &mut String, store: &StrStore, names: &TyVarNames, lab: Label, ty: &Ty) {
   buf.push_str(&show_lab(store, lab));
   buf.push_str(" : ");
-  show_ty_impl(buf, store, ty, TyPrec::Arrow);
+  show_ty_impl(buf, store, names, ty, TyPrec::Arrow);
 }
 
 /// A specialized Result type that many functions doing static analysis return.
@@ -442,9 +696,14 @@ impl Subst {
     }
     if let Some(syms) = self.overload.remove(&tv) {
       let syms = match &ty {
+        // a user-annotated type variable may not be constrained to be one of the overloaded types.
         Ty::Var(other) => {
-          self.overload.insert(*other, syms);
-          None
+          if self.is_bound(other) {
+            Some(syms)
+          } else {
+            self.overload.insert(*other, syms);
+            None
+          }
         }
         Ty::Record(_) | Ty::Arrow(_, _) => Some(syms),
         Ty::Ctor(args, sym) => {
@@ -636,6 +895,24 @@ impl TyScheme {
     }
   }
 
+  /// Returns whether this, as a type function, admits equality. It does if its type is an equality
+  /// type when its bound type variables are equality type variables.
+  pub fn admits_equality(&self, tys: &Tys) -> bool {
+    let mut subst = Subst::default();
+    for &tv in self.ty_vars.iter() {
+      subst.insert(
+        tv,
+        Ty::Var(TyVar {
+          equality: true,
+          ..tv
+        }),
+      );
+    }
+    let mut ty = self.ty.clone();
+    ty.apply(&subst);
+    ty.is_equality(tys)
+  }
+
   /// Returns the free type variables in this.
   pub fn free_ty_vars(&self) -> TyVarSet {
     self
@@ -791,6 +1068,8 @@ pub struct ValInfo {
   pub ty_scheme: TyScheme,
   /// Its identifier status.
   pub id_status: IdStatus,
+  /// Where it was defined, if it was defined in user code.
+  pub def: Option<Def>,
 }
 
 impl ValInfo {
@@ -799,6 +1078,7 @@ impl ValInfo {
     Self {
       ty_scheme,
       id_status: IdStatus::Ctor,
+      def: None,
     }
   }
 
@@ -807,6 +1087,7 @@ impl ValInfo {
     Self {
       ty_scheme: TyScheme::mono(Ty::EXN),
       id_status: IdStatus::Exn,
+      def: None,
     }
   }
 
@@ -817,6 +1098,7 @@ impl ValInfo {
     Self {
       ty_scheme: TyScheme::mono(Ty::Arrow(ty.into(), Ty::EXN.into())),
       id_status: IdStatus::Exn,
+      def: None,
     }
   }
 
@@ -825,6 +1107,7 @@ impl ValInfo {
     Self {
       ty_scheme,
       id_status: IdStatus::Val,
+      def: None,
     }
   }
 }
@@ -842,6 +1125,8 @@ pub struct Env {
   pub ty_env: TyEnv,
   /// The values defined in this structure.
   pub val_env: ValEnv,
+  /// Where this was defined as a structure, if it was defined in user code.
+  pub def: Option<Def>,
 }
 
 impl Env {
@@ -890,6 +1175,25 @@ impl Env {
     }
   }
 
+  /// Returns whether this, or an environment in this, binds both the name defined at `def` and
+  /// another name, in the same namespace, which is shown as `name`.
+  pub fn collides(&self, store: &StrStore, st: &State, def: Def, name: &str) -> bool {
+    let str_env = self.str_env.iter().map(|(&x, env)| (x, env.def));
+    let ty_env = self
+      .ty_env
+      .inner
+      .iter()
+      .map(|(&x, &sym)| (x, st.sym_def(sym)));
+    let val_env = self.val_env.iter().map(|(&x, vi)| (x, vi.def));
+    collides_in(store, def, name, str_env)
+      || collides_in(store, def, name, ty_env)
+      || collides_in(store, def, name, val_env)
+      || self
+        .str_env
+        .values()
+        .any(|env| env.collides(store, st, def, name))
+  }
+
   /// Returns the free type variables in this.
   pub fn free_ty_vars(&self, tys: &Tys) -> TyVarSet {
     self
@@ -907,12 +1211,25 @@ impl Env {
   }
 }
 
+/// Returns whether the names in `iter` include both the name defined at `def`, and another name
+/// shown as `name`.
+fn collides_in<I>(store: &StrStore, def: Def, name: &str, iter: I) -> bool
+where
+  I: Iterator<Item = (StrRef, Option<Def>)> + Clone,
+{
+  iter.clone().any(|(_, x)| x == Some(def))
+    && iter
+      .filter(|&(_, x)| x != Some(def))
+      .any(|(x, _)| store.get(x) == name)
+}
+
 impl From<ValEnv> for Env {
   fn from(val_env: ValEnv) -> Self {
     Self {
       str_env: StrEnv::new(),
       ty_env: TyEnv::default(),
       val_env,
+      def: None,
     }
   }
 }
@@ -923,6 +1240,7 @@ impl From<TyEnv> for Env {
       str_env: StrEnv::new(),
       ty_env,
       val_env: ValEnv::new(),
+      def: None,
     }
   }
 }
@@ -933,6 +1251,7 @@ impl From<StrEnv> for Env {
       str_env,
       ty_env: TyEnv::default(),
       val_env: ValEnv::new(),
+      def: None,
     }
   }
 }
@@ -970,6 +1289,8 @@ pub struct Sig {
   /// The environment, which may make reference to the bound type names as well as type names from
   /// the surrounding environment.
   pub env: Env,
+  /// Where it was defined, if it was defined in user code.
+  pub def: Option<Def>,
 }
 
 /// A functor signature. Note that in the Definition this is TyNameSet x (Env x Sig) but that's
@@ -979,6 +1300,8 @@ pub struct Sig {
 pub struct FunSig {
   pub input: Sig,
   pub output: Sig,
+  /// Where it was defined.
+  pub def: Option<Def>,
 }
 
 /// A signature environment.
@@ -987,9 +1310,9 @@ pub type SigEnv = HashMap<StrRef, Sig>;
 /// A functor environment.
 pub type FunEnv = HashMap<StrRef, FunSig>;
 
-/// A basis. There's one of these in the whole program, since it basically represents the entire
-/// program.
-#[derive(Clone)]
+/// A basis. There's usually one of these in the whole program, since it basically represents the
+/// entire program. But projects with explicit scoping, like MLB files, may have many.
+#[derive(Clone, Default)]
 pub struct Basis {
   pub fun_env: FunEnv,
   pub sig_env: SigEnv,
@@ -997,6 +1320,13 @@ pub struct Basis {
 }
 
 impl Basis {
+  /// Extends a basis with another. `other` overwrites `self`.
+  pub fn extend(&mut self, other: Self) {
+    self.fun_env.extend(other.fun_env);
+    self.sig_env.extend(other.sig_env);
+    self.env.extend(other.env);
+  }
+
   /// Apply a substitution to this.
   pub fn apply(&mut self, subst: &Subst, tys: &mut Tys) {
     for fun_sig in self.fun_env.values_mut() {
@@ -1009,6 +1339,20 @@ impl Basis {
     self.env.apply(subst, tys);
   }
 
+  /// Returns whether this, or an environment in this, binds both the name defined at `def` and
+  /// another name, in the same namespace, which is shown as `name`.
+  pub fn collides(&self, store: &StrStore, st: &State, def: Def, name: &str) -> bool {
+    let fun_env = self.fun_env.iter().map(|(&x, fun_sig)| (x, fun_sig.def));
+    let sig_env = self.sig_env.iter().map(|(&x, sig)| (x, sig.def));
+    collides_in(store, def, name, fun_env)
+      || collides_in(store, def, name, sig_env)
+      || self.env.collides(store, st, def, name)
+      || self
+        .sig_env
+        .values()
+        .any(|x| x.env.collides(store, st, def, name))
+  }
+
   /// Return the free type variables in this. Should always be empty, as per the Definition.
   pub fn free_ty_vars(&self, tys: &Tys) -> TyVarSet {
     self
@@ -1054,6 +1398,28 @@ pub struct State {
   /// The types that 'have been generated' and information about them. Invariant: Always grows in
   /// size.
   pub tys: Tys,
+  /// The errors encountered so far. We keep checking after encountering an error, so there may be
+  /// many of these.
+  errors: Vec<Located<Error>>,
+  /// The file we're currently checking.
+  pub file: usize,
+  /// Where the symbols were defined, if they were defined in user code.
+  sym_defs: HashMap<Sym, Def>,
+  /// The definition sites of the names defined so far. Invariant: Always grows in size.
+  pub defs: Vec<Def>,
+  /// The names used so far, with where each was used (in which file) and where it was defined.
+  /// Invariant: Always grows in size.
+  pub uses: Vec<(usize, Loc, Def)>,
+  /// The types of the expressions and patterns checked so far, with the file and location of each.
+  /// Invariant: Always grows in size.
+  pub tys_at: Vec<(usize, Loc, Ty)>,
+  /// The file and byte index at which to record what is in scope, if any.
+  pub scope_at: Option<(usize, usize)>,
+  /// What is in scope at `scope_at`, and whether that is inside an expression.
+  pub scope: Option<(Env, bool)>,
+  /// The arms missing from the non-exhaustive matches checked so far, with the file of each.
+  /// Invariant: Always grows in size.
+  pub missing_arms: Vec<(usize, MissingArms)>,
 }
 
 impl State {
@@ -1064,11 +1430,81 @@ impl State {
     TyVar { id, equality }
   }
 
-  /// Returns a fresh symbol.
+  /// Returns a fresh symbol, defined at the location of `name`.
   pub fn new_sym(&mut self, name: Located<StrRef>) -> Sym {
     let id = Some(name.loc.wrap(self.next_sym));
     self.next_sym += 1;
-    Sym { id, name: name.val }
+    let ret = Sym { id, name: name.val };
+    let def = self.def(name.loc);
+    self.sym_defs.insert(ret, def);
+    ret
+  }
+
+  /// Returns a fresh symbol generated at `loc` from `old`, like by opaque ascription. It is
+  /// considered to be defined wherever `old` was.
+  pub fn new_sym_from(&mut self, old: Sym, loc: Loc) -> Sym {
+    let id = Some(loc.wrap(self.next_sym));
+    self.next_sym += 1;
+    let ret = Sym {
+      id,
+      name: old.name(),
+    };
+    if let Some(def) = self.sym_def(old) {
+      self.sym_defs.insert(ret, def);
+    }
+    ret
+  }
+
+  /// Records and returns a definition site at `loc` in the current file.
+  pub fn def(&mut self, loc: Loc) -> Def {
+    let ret = Def {
+      file: self.file,
+      loc,
+    };
+    self.defs.push(ret);
+    ret
+  }
+
+  /// Returns where the symbol was defined, if it was defined in user code.
+  pub fn sym_def(&self, sym: Sym) -> Option<Def> {
+    self.sym_defs.get(&sym).copied()
+  }
+
+  /// Records a use, at `loc` in the current file, of a name defined at `def`.
+  pub fn use_def(&mut self, loc: Loc, def: Option<Def>) {
+    if let Some(def) = def {
+      self.uses.push((self.file, loc, def));
+    }
+  }
+
+  /// Records that the expression or pattern at `loc` in the current file has type `ty`.
+  pub fn record_ty(&mut self, loc: Loc, ty: &Ty) {
+    self.tys_at.push((self.file, loc, ty.clone()));
+  }
+
+  /// Records the arms missing from a non-exhaustive match in the current file.
+  pub fn record_missing_arms(&mut self, arms: MissingArms) {
+    self.missing_arms.push((self.file, arms));
+  }
+
+  /// Records that `env` is in scope at `loc` in the current file, if `loc` contains `scope_at`.
+  /// Since the innermost constructs are checked last, the innermost scope is the one recorded.
+  pub fn record_scope(&mut self, loc: Loc, env: &Env, in_exp: bool) {
+    if let Some((file, idx)) = self.scope_at {
+      let range: std::ops::Range<usize> = loc.into();
+      if file == self.file && range.start <= idx && idx <= range.end {
+        self.scope = Some((env.clone(), in_exp));
+      }
+    }
+  }
+
+  /// Returns whether `loc` in the current file ends before `scope_at`, in which case what `loc`
+  /// declares is in scope there.
+  pub fn is_before_scope(&self, loc: Loc) -> bool {
+    match self.scope_at {
+      Some((file, idx)) => file == self.file && std::ops::Range::<usize>::from(loc).end < idx,
+      None => false,
+    }
   }
 
   /// Returns an opaque type that contains information about what symbols have been generated.
@@ -1082,6 +1518,38 @@ impl State {
   pub fn unify(&mut self, loc: Loc, want: Ty, got: Ty) -> Result<()> {
     self.subst.unify(loc, &self.tys, want, got)
   }
+
+  /// Records an error. The caller should then continue checking as best it can.
+  pub fn err(&mut self, e: Located<Error>) {
+    self.errors.push(e);
+  }
+
+  /// Returns `x` if `res` is `Ok(x)`. Else records the error and returns the default value for `T`.
+  pub fn recover<T: Default>(&mut self, res: Result<T>) -> T {
+    match res {
+      Ok(x) => x,
+      Err(e) => {
+        self.err(e);
+        T::default()
+      }
+    }
+  }
+
+  /// Returns and removes all the errors recorded so far, in source order.
+  pub fn take_errors(&mut self) -> Vec<Located<Error>> {
+    let mut ret = std::mem::take(&mut self.errors);
+    ret.sort_by_key(|e| e.loc);
+    ret
+  }
+}
+
+/// A place in a particular file where something was defined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Def {
+  /// The file, as given to `Statics`.
+  pub file: usize,
+  /// The location in that file.
+  pub loc: Loc,
 }
 
 /// Contains information about what symbols have been generated.
@@ -1090,6 +1558,14 @@ pub struct GeneratedSyms {
 }
 
 impl GeneratedSyms {
+  /// Returns whether `sym` was generated before this `GeneratedSyms` was.
+  pub fn contains_sym(&self, sym: Sym) -> bool {
+    match sym.id {
+      None => true,
+      Some(id) => id.val < self.next_sym,
+    }
+  }
+
   /// Returns whether all symbols in `ty_names` were generated before this `GeneratedSyms` was.
   pub fn contains(&self, ty_names: &TyNameSet) -> bool {
     for sym in ty_names {
@@ -1106,6 +1582,20 @@ impl GeneratedSyms {
   }
 }
 
+/// The arms missing from a non-exhaustive `case`, `fn`, or `fun`.
+#[derive(Debug)]
+pub struct MissingArms {
+  /// The location of the match.
+  pub loc: Loc,
+  /// The location of the start of the last arm.
+  pub last_arm: Loc,
+  /// The name and number of arguments of the function, if the match is a `fun`.
+  pub fun: Option<(StrRef, usize)>,
+  /// The patterns of the missing arms. For a `fun` with many arguments, each is the tuple of the
+  /// arguments.
+  pub pats: Vec<Pat>,
+}
+
 /// A pattern, for the purposes of static analysis. See exhaustive.rs.
 #[derive(Debug, Clone)]
 pub enum Pat {
@@ -1121,13 +1611,20 @@ impl Pat {
     Self::Con(con, vec![])
   }
 
-  /// Returns a record pattern. Requires the patterns be sorted by label.
-  pub fn record(mut pats: Vec<Pat>) -> Self {
+  /// Returns a record pattern.
+  pub fn record(rows: BTreeMap<Label, Pat>) -> Self {
+    let (labs, pats) = rows.into_iter().unzip();
+    Self::Con(Con::Record(labs), pats)
+  }
+
+  /// Returns a tuple pattern, or the pattern itself if there is only one.
+  pub fn tuple(mut pats: Vec<Pat>) -> Self {
     if pats.len() == 1 {
       // may happen in the desugaring of `Fun`.
       pats.pop().unwrap()
     } else {
-      Self::Con(Con::Record(pats.len()), pats)
+      let labs = (0..pats.len()).map(Label::tuple).collect();
+      Self::Con(Con::Record(labs), pats)
     }
   }
 }
@@ -1135,16 +1632,17 @@ impl Pat {
 /// A constructor for a pattern. It is a bit confusing to have both 'Con' and 'Ctor'. We originally
 /// used 'Ctor' to mean 'constructor', and then adopted 'Con' as well from reading the paper which
 /// was the basis of the exhaustiveness checker.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Con {
   Int(i32),
   Word(i32),
   String(StrRef),
   Char(u8),
-  /// This should never be used directly, use `Pat::record` instead. The usize is the arity.
-  Record(usize),
-  /// A constructor from a `datatype` or an `exception`.
-  Ctor(StrRef, Span),
+  /// This should never be used directly, use `Pat::record` or `Pat::tuple` instead. The labels are
+  /// sorted.
+  Record(Vec<Label>),
+  /// A constructor from a `datatype` or an `exception`, with the symbol of the type it constructs.
+  Ctor(StrRef, Sym, Span),
 }
 
 /// A measure of how many constructors exist for a type.
@@ -1161,7 +1659,7 @@ impl Con {
       Self::Int(_) | Self::Word(_) | Self::String(_) => Span::PosInf,
       Self::Char(_) => Span::Finite(256),
       Self::Record(_) => Span::Finite(1),
-      Self::Ctor(_, s) => s,
+      Self::Ctor(_, _, s) => s,
     }
   }
 }
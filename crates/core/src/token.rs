@@ -186,6 +186,51 @@ impl Token {
       Self::EOF => "end of file",
     }
   }
+
+  /// Returns whether this is a reserved word which may begin a declaration.
+  pub fn begins_dec(&self) -> bool {
+    matches!(
+      self,
+      Self::Abstype
+        | Self::Datatype
+        | Self::Exception
+        | Self::Fun
+        | Self::Functor
+        | Self::Infix
+        | Self::Infixr
+        | Self::Local
+        | Self::Nonfix
+        | Self::Open
+        | Self::Signature
+        | Self::Structure
+        | Self::Type
+        | Self::Val
+    )
+  }
+
+  /// Returns whether this is a reserved word which may appear in an expression, outside of any
+  /// declarations in the expression.
+  pub fn in_exp(&self) -> bool {
+    matches!(
+      self,
+      Self::Andalso
+        | Self::Case
+        | Self::Do
+        | Self::Else
+        | Self::End
+        | Self::Fn
+        | Self::Handle
+        | Self::If
+        | Self::In
+        | Self::Let
+        | Self::Of
+        | Self::Op
+        | Self::Orelse
+        | Self::Raise
+        | Self::Then
+        | Self::While
+    )
+  }
 }
 
 /// This is here (and not in ast.rs) because we know when lexing whether something is a type var.
@@ -1,18 +1,31 @@
 //! Types for messages to and from the server.
 
 use lsp_types::{
-  DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
-  DidSaveTextDocumentParams, InitializeParams, InitializeResult, NumberOrString,
-  PublishDiagnosticsParams,
+  CancelParams, CodeActionParams, CodeActionResponse, CompletionItem, CompletionParams,
+  DidChangeConfigurationParams, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+  DidOpenTextDocumentParams, DidSaveTextDocumentParams, DocumentSymbolParams,
+  DocumentSymbolResponse, GotoDefinitionParams, Hover, HoverParams, InitializeParams,
+  InitializeResult, Location, LogMessageParams, NumberOrString, PublishDiagnosticsParams,
+  ReferenceParams, RenameParams, ShowMessageParams, SymbolInformation, WorkspaceEdit,
+  WorkspaceSymbolParams,
 };
 use serde::de::DeserializeOwned;
 use serde_json::{from_slice, from_value, json, to_value, to_vec, Error, Map, Value};
+use std::mem::take;
 
 const JSON_RPC_VERSION: &str = "2.0";
 
 pub enum IncomingRequestParams {
-  Initialize(InitializeParams),
+  Initialize(Box<InitializeParams>),
   Shutdown,
+  Definition(GotoDefinitionParams),
+  Hover(HoverParams),
+  References(ReferenceParams),
+  Rename(RenameParams),
+  Completion(CompletionParams),
+  DocumentSymbol(DocumentSymbolParams),
+  WorkspaceSymbol(WorkspaceSymbolParams),
+  CodeAction(CodeActionParams),
 }
 
 pub struct Request<Params> {
@@ -29,6 +42,8 @@ impl<Params> Request<Params> {
 pub enum IncomingNotification {
   Initialized,
   Exit,
+  Cancel(CancelParams),
+  ChangeConfiguration(DidChangeConfigurationParams),
   TextDocOpen(DidOpenTextDocumentParams),
   TextDocChange(DidChangeTextDocumentParams),
   TextDocSave(DidSaveTextDocumentParams),
@@ -38,57 +53,119 @@ pub enum IncomingNotification {
 pub enum Incoming {
   Request(Request<IncomingRequestParams>),
   Notification(IncomingNotification),
+  /// A message that was invalid, and the error response to send for it.
+  Invalid(Response),
 }
 
 impl Incoming {
-  fn request(id: NumberOrString, params: IncomingRequestParams) -> Self {
-    Self::Request(Request::new(id, params))
+  fn invalid<M>(id: Option<NumberOrString>, code: ErrorCode, message: M) -> Self
+  where
+    M: std::fmt::Display,
+  {
+    Self::Invalid(Response {
+      id,
+      res: Err(ResponseError::new(code, message)),
+    })
   }
 
+  /// Parses a message. Returns `None` if the message should be ignored, which is the case for
+  /// responses and for unknown or invalid notifications.
   pub fn try_parse(bs: &[u8]) -> Option<Self> {
-    let mut val: Value = from_slice(bs).ok()?;
-    if val.get("jsonrpc")?.as_str()? != JSON_RPC_VERSION {
+    let mut val: Value = match from_slice(bs) {
+      Ok(x) => x,
+      Err(e) => return Some(Self::invalid(None, ErrorCode::ParseError, e)),
+    };
+    if val.get("method").is_none() && (val.get("result").is_some() || val.get("error").is_some()) {
       return None;
     }
-    let ret = match val.get("method")?.as_str()? {
-      "initialize" => Incoming::request(
-        get_id(&mut val)?,
-        IncomingRequestParams::Initialize(get_params(&mut val)?),
-      ),
-      "initialized" => Incoming::Notification(IncomingNotification::Initialized),
-      "shutdown" => Incoming::request(get_id(&mut val)?, IncomingRequestParams::Shutdown),
-      "exit" => Incoming::Notification(IncomingNotification::Exit),
-      "textDocument/didOpen" => {
-        Incoming::Notification(IncomingNotification::TextDocOpen(get_params(&mut val)?))
-      }
-      "textDocument/didClose" => {
-        Incoming::Notification(IncomingNotification::TextDocClose(get_params(&mut val)?))
-      }
-      "textDocument/didChange" => {
-        Incoming::Notification(IncomingNotification::TextDocChange(get_params(&mut val)?))
-      }
-      "textDocument/didSave" => {
-        Incoming::Notification(IncomingNotification::TextDocSave(get_params(&mut val)?))
-      }
-      _ => return None,
+    let id = match val.get_mut("id").map(take) {
+      None => None,
+      Some(id) => match from_value(id) {
+        Ok(x) => Some(x),
+        Err(e) => return Some(Self::invalid(None, ErrorCode::InvalidRequest, e)),
+      },
+    };
+    if val.get("jsonrpc").and_then(Value::as_str) != Some(JSON_RPC_VERSION) {
+      let e = format!("jsonrpc was not {}", JSON_RPC_VERSION);
+      return Some(Self::invalid(id, ErrorCode::InvalidRequest, e));
+    }
+    let method = match val.get("method").and_then(Value::as_str) {
+      Some(x) => x.to_owned(),
+      None => return Some(Self::invalid(id, ErrorCode::InvalidRequest, "no method")),
+    };
+    let params = val.get_mut("params").map_or(Value::Null, take);
+    let ret = match id {
+      Some(id) => match request(&method, params) {
+        Ok(params) => Self::Request(Request::new(id, params)),
+        Err(e) => Self::Invalid(Response {
+          id: Some(id),
+          res: Err(e),
+        }),
+      },
+      None => Self::Notification(notification(&method, params)?),
     };
     Some(ret)
   }
 }
 
-fn get_id(val: &mut Value) -> Option<NumberOrString> {
-  from_value(std::mem::take(val.get_mut("id")?)).ok()
+/// Returns the params of the request with this method, or an error if there is no such method or
+/// the params were invalid.
+fn request(method: &str, params: Value) -> Result<IncomingRequestParams, ResponseError> {
+  let ret = match method {
+    "initialize" => IncomingRequestParams::Initialize(Box::new(get_params(params)?)),
+    "shutdown" => IncomingRequestParams::Shutdown,
+    "textDocument/definition" => IncomingRequestParams::Definition(get_params(params)?),
+    "textDocument/hover" => IncomingRequestParams::Hover(get_params(params)?),
+    "textDocument/references" => IncomingRequestParams::References(get_params(params)?),
+    "textDocument/rename" => IncomingRequestParams::Rename(get_params(params)?),
+    "textDocument/completion" => IncomingRequestParams::Completion(get_params(params)?),
+    "textDocument/documentSymbol" => IncomingRequestParams::DocumentSymbol(get_params(params)?),
+    "workspace/symbol" => IncomingRequestParams::WorkspaceSymbol(get_params(params)?),
+    "textDocument/codeAction" => IncomingRequestParams::CodeAction(get_params(params)?),
+    _ => {
+      let e = format!("unknown method: {}", method);
+      return Err(ResponseError::new(ErrorCode::MethodNotFound, e));
+    }
+  };
+  Ok(ret)
 }
 
-fn get_params<T>(val: &mut Value) -> Option<T>
+/// Returns the notification with this method, or `None` if there is no such method or the params
+/// were invalid.
+fn notification(method: &str, params: Value) -> Option<IncomingNotification> {
+  let ret = match method {
+    "initialized" => IncomingNotification::Initialized,
+    "exit" => IncomingNotification::Exit,
+    "$/cancelRequest" => IncomingNotification::Cancel(get_params(params).ok()?),
+    "workspace/didChangeConfiguration" => {
+      IncomingNotification::ChangeConfiguration(get_params(params).ok()?)
+    }
+    "textDocument/didOpen" => IncomingNotification::TextDocOpen(get_params(params).ok()?),
+    "textDocument/didClose" => IncomingNotification::TextDocClose(get_params(params).ok()?),
+    "textDocument/didChange" => IncomingNotification::TextDocChange(get_params(params).ok()?),
+    "textDocument/didSave" => IncomingNotification::TextDocSave(get_params(params).ok()?),
+    _ => return None,
+  };
+  Some(ret)
+}
+
+fn get_params<T>(params: Value) -> Result<T, ResponseError>
 where
   T: DeserializeOwned,
 {
-  from_value(std::mem::take(val.get_mut("params")?)).ok()
+  from_value(params).map_err(|e| ResponseError::new(ErrorCode::InvalidParams, e))
 }
 
 pub enum ResponseSuccess {
-  Initialize(InitializeResult),
+  Initialize(Box<InitializeResult>),
+  Definition(Option<Location>),
+  Hover(Option<Hover>),
+  References(Option<Vec<Location>>),
+  Rename(Option<WorkspaceEdit>),
+  Completion(Option<Vec<CompletionItem>>),
+  DocumentSymbol(Option<DocumentSymbolResponse>),
+  WorkspaceSymbol(Option<Vec<SymbolInformation>>),
+  CodeAction(Option<CodeActionResponse>),
   Null,
 }
 
@@ -112,6 +189,18 @@ pub struct ResponseError {
   pub message: String,
 }
 
+impl ResponseError {
+  pub fn new<M>(code: ErrorCode, message: M) -> Self
+  where
+    M: std::fmt::Display,
+  {
+    Self {
+      code,
+      message: message.to_string(),
+    }
+  }
+}
+
 pub struct Response {
   pub id: Option<NumberOrString>,
   pub res: Result<ResponseSuccess, ResponseError>,
@@ -127,6 +216,14 @@ impl Response {
         "result",
         match good {
           ResponseSuccess::Initialize(x) => to_value(x)?,
+          ResponseSuccess::Definition(x) => to_value(x)?,
+          ResponseSuccess::Hover(x) => to_value(x)?,
+          ResponseSuccess::References(x) => to_value(x)?,
+          ResponseSuccess::Rename(x) => to_value(x)?,
+          ResponseSuccess::Completion(x) => to_value(x)?,
+          ResponseSuccess::DocumentSymbol(x) => to_value(x)?,
+          ResponseSuccess::WorkspaceSymbol(x) => to_value(x)?,
+          ResponseSuccess::CodeAction(x) => to_value(x)?,
           ResponseSuccess::Null => Value::Null,
         },
       ),
@@ -142,6 +239,8 @@ impl Response {
 
 pub enum OutgoingNotification {
   PublishDiagnostics(PublishDiagnosticsParams),
+  ShowMessage(ShowMessageParams),
+  LogMessage(LogMessageParams),
 }
 
 impl OutgoingNotification {
@@ -150,6 +249,8 @@ impl OutgoingNotification {
     map.insert("jsonrpc".to_owned(), JSON_RPC_VERSION.into());
     let (method, params) = match self {
       Self::PublishDiagnostics(params) => ("textDocument/publishDiagnostics", to_value(&params)?),
+      Self::ShowMessage(params) => ("window/showMessage", to_value(&params)?),
+      Self::LogMessage(params) => ("window/logMessage", to_value(&params)?),
     };
     map.insert("method".to_owned(), method.into());
     map.insert("params".to_owned(), params);
@@ -0,0 +1,199 @@
+//! Settings for the server, which the client sends in the `initializationOptions` of the initialize
+//! request and in `workspace/didChangeConfiguration`. They are a JSON object like:
+//!
+//! ```json
+//! {
+//!   "manifestPath": "src/sources.mlb",
+//!   "lints": { "unreachablePattern": false },
+//!   "nonExhaustive": "error",
+//!   "maxDiagnostics": 20,
+//!   "logLevel": "info"
+//! }
+//! ```
+//!
+//! Every key is optional. The object may also be under a `millet` key, as editors often send all
+//! the settings they have, by extension. New settings are merged into the ones we already have:
+//! keys which are not sent keep their values, and keys which are `null` go back to the default.
+
+use crate::log::Level;
+use millet_core::statics::{Lint, Severity};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// The settings.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+  /// The project file, relative to the workspace root. If `None`, it is found with `project::find`.
+  pub manifest_path: Option<PathBuf>,
+  /// The lints that are turned off.
+  pub disabled_lints: HashSet<Lint>,
+  /// The severity of non-exhaustive matches and bindings, if not the usual one.
+  pub non_exhaustive: Option<Severity>,
+  /// The most diagnostics to report for each file, if there is a limit.
+  pub max_diagnostics: Option<usize>,
+  /// How much to log.
+  pub log_level: Level,
+}
+
+impl Config {
+  /// Updates the settings with those in `val`. Returns messages for the settings that were invalid,
+  /// which are ignored.
+  pub fn update(&mut self, val: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    let val = val.get("millet").unwrap_or(val);
+    let obj = match val {
+      Value::Null => return errors,
+      Value::Object(x) => x,
+      _ => {
+        errors.push("expected the settings to be an object".to_owned());
+        return errors;
+      }
+    };
+    for (key, val) in obj {
+      let valid = match key.as_str() {
+        "manifestPath" => match val.as_str() {
+          Some(x) => {
+            self.manifest_path = Some(x.into());
+            true
+          }
+          None if val.is_null() => {
+            self.manifest_path = None;
+            true
+          }
+          None => false,
+        },
+        "lints" => match val.as_object() {
+          Some(lints) => {
+            for (name, val) in lints {
+              match (lint_from_name(name), val.as_bool()) {
+                (Some(lint), Some(true)) => {
+                  self.disabled_lints.remove(&lint);
+                }
+                (Some(lint), Some(false)) => {
+                  self.disabled_lints.insert(lint);
+                }
+                (None, _) => errors.push(format!("unknown lint: {}", name)),
+                (Some(_), None) => errors.push(format!("expected lints.{} to be a bool", name)),
+              }
+            }
+            true
+          }
+          None if val.is_null() => {
+            self.disabled_lints.clear();
+            true
+          }
+          None => false,
+        },
+        "nonExhaustive" => match val.as_str() {
+          Some("error") => {
+            self.non_exhaustive = Some(Severity::Error);
+            true
+          }
+          Some("warning") => {
+            self.non_exhaustive = Some(Severity::Warning);
+            true
+          }
+          None if val.is_null() => {
+            self.non_exhaustive = None;
+            true
+          }
+          _ => false,
+        },
+        "maxDiagnostics" => match val.as_u64() {
+          Some(x) => {
+            self.max_diagnostics = Some(x as usize);
+            true
+          }
+          None if val.is_null() => {
+            self.max_diagnostics = None;
+            true
+          }
+          None => false,
+        },
+        "logLevel" => match val.as_str().and_then(Level::from_name) {
+          Some(x) => {
+            self.log_level = x;
+            true
+          }
+          None if val.is_null() => {
+            self.log_level = Level::default();
+            true
+          }
+          None => false,
+        },
+        _ => {
+          errors.push(format!("unknown setting: {}", key));
+          continue;
+        }
+      };
+      if !valid {
+        errors.push(format!("invalid value for {}: {}", key, val));
+      }
+    }
+    errors
+  }
+
+  /// Returns whether the lint is turned on.
+  pub fn lint_enabled(&self, lint: Lint) -> bool {
+    !self.disabled_lints.contains(&lint)
+  }
+}
+
+fn lint_from_name(name: &str) -> Option<Lint> {
+  let ret = match name {
+    "nonExhaustiveMatch" => Lint::NonExhaustiveMatch,
+    "nonExhaustiveBinding" => Lint::NonExhaustiveBinding,
+    "unreachablePattern" => Lint::UnreachablePattern,
+    _ => return None,
+  };
+  Some(ret)
+}
+
+#[test]
+fn test_config() {
+  let val = serde_json::json!({
+    "millet": {
+      "manifestPath": "src/sources.mlb",
+      "lints": { "unreachablePattern": false, "nonExhaustiveMatch": true, "frobnicate": false },
+      "nonExhaustive": "error",
+      "maxDiagnostics": 3,
+      "logLevel": "loud",
+    }
+  });
+  let mut config = Config::default();
+  let errors = config.update(&val);
+  assert_eq!(config.manifest_path, Some(PathBuf::from("src/sources.mlb")));
+  assert!(!config.lint_enabled(Lint::UnreachablePattern));
+  assert!(config.lint_enabled(Lint::NonExhaustiveMatch));
+  assert_eq!(config.non_exhaustive, Some(Severity::Error));
+  assert_eq!(config.max_diagnostics, Some(3));
+  assert_eq!(config.log_level, Level::default());
+  assert_eq!(
+    errors,
+    vec![
+      "unknown lint: frobnicate".to_owned(),
+      "invalid value for logLevel: \"loud\"".to_owned(),
+    ]
+  );
+}
+
+#[test]
+fn test_config_update() {
+  let mut config = Config::default();
+  let val = serde_json::json!({
+    "manifestPath": "src/sources.mlb",
+    "lints": { "unreachablePattern": false },
+    "maxDiagnostics": 3,
+  });
+  assert!(config.update(&val).is_empty());
+  let val = serde_json::json!({
+    "lints": { "nonExhaustiveMatch": false },
+    "maxDiagnostics": null,
+  });
+  assert!(config.update(&val).is_empty());
+  assert_eq!(config.manifest_path, Some(PathBuf::from("src/sources.mlb")));
+  assert!(!config.lint_enabled(Lint::UnreachablePattern));
+  assert!(!config.lint_enabled(Lint::NonExhaustiveMatch));
+  assert_eq!(config.max_diagnostics, None);
+}
@@ -14,7 +14,9 @@ pub fn read_stdin(s: Sender<Incoming>) {
   let mut content_len: Option<usize> = None;
   loop {
     buf.clear();
-    assert_ne!(stdin.read_until(b'\n', &mut buf).unwrap(), 0);
+    if stdin.read_until(b'\n', &mut buf).unwrap() == 0 {
+      break;
+    }
     if let Some(n) = content_length(&buf) {
       content_len = Some(n);
       continue;
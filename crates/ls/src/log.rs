@@ -0,0 +1,83 @@
+//! Logging to the client, with `window/logMessage`.
+
+use crate::comm::OutgoingNotification;
+use crate::state::Done;
+use crossbeam_channel::Sender;
+use lsp_types::{LogMessageParams, MessageType};
+
+/// How much to log. Each level logs the messages of the levels before it too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Level {
+  Off,
+  Error,
+  #[default]
+  Warning,
+  Info,
+  Log,
+}
+
+impl Level {
+  /// Returns the level with this name, as in the `logLevel` initialization option.
+  pub fn from_name(name: &str) -> Option<Self> {
+    let ret = match name {
+      "off" => Self::Off,
+      "error" => Self::Error,
+      "warning" => Self::Warning,
+      "info" => Self::Info,
+      "log" => Self::Log,
+      _ => return None,
+    };
+    Some(ret)
+  }
+
+  fn message_type(self) -> Option<MessageType> {
+    match self {
+      Self::Off => None,
+      Self::Error => Some(MessageType::Error),
+      Self::Warning => Some(MessageType::Warning),
+      Self::Info => Some(MessageType::Info),
+      Self::Log => Some(MessageType::Log),
+    }
+  }
+}
+
+/// Sends messages at or before its level to the client. The messages are sent as work done in the
+/// background, so they can be sent from any thread.
+#[derive(Clone)]
+pub struct Logger {
+  level: Level,
+  done: Sender<Done>,
+}
+
+impl Logger {
+  /// Returns a new Logger that sends messages to `done`.
+  pub fn new(level: Level, done: Sender<Done>) -> Self {
+    Self { level, done }
+  }
+
+  /// Sets the level.
+  pub fn set_level(&mut self, level: Level) {
+    self.level = level;
+  }
+
+  /// Logs a message at `level`.
+  pub fn log<M>(&self, level: Level, message: M)
+  where
+    M: Into<String>,
+  {
+    if level > self.level {
+      return;
+    }
+    let typ = match level.message_type() {
+      None => return,
+      Some(x) => x,
+    };
+    let params = LogMessageParams {
+      typ,
+      message: message.into(),
+    };
+    let _ = self
+      .done
+      .send(Done::Notification(OutgoingNotification::LogMessage(params)));
+  }
+}
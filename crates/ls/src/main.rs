@@ -1,9 +1,16 @@
 //! A language server for Standard ML.
 
 mod comm;
+mod config;
 mod headers;
 mod io;
+mod log;
+mod pool;
 mod state;
+mod symbols;
+
+#[cfg(test)]
+mod tests;
 
 fn main() {
   let (s_inc, r_inc) = crossbeam_channel::unbounded();
@@ -16,20 +23,25 @@ fn main() {
     .name("write_stdout".to_owned())
     .spawn(move || io::write_stdout(r_out))
     .unwrap();
-  let mut st = state::State::new();
+  let (s_done, r_done) = crossbeam_channel::unbounded();
+  let mut st = state::State::new(s_done);
   let exit_ok = loop {
-    match r_inc.recv().unwrap() {
-      comm::Incoming::Request(req) => {
-        let res = st.handle_request(req);
-        s_out.send(comm::Outgoing::Response(res)).unwrap();
-      }
-      comm::Incoming::Notification(notif) => match st.handle_notification(notif) {
-        None => {}
-        Some(action) => match action {
-          state::Action::Exit(x) => break x,
-          state::Action::Respond(x) => s_out.send(*x).unwrap(),
-        },
+    let action = crossbeam_channel::select! {
+      recv(r_inc) -> msg => match msg {
+        Ok(msg) => st.handle(msg),
+        // stdin was closed without an exit notification.
+        Err(_) => Some(state::Action::Exit(false)),
       },
+      recv(r_done) -> done => st.handle_done(done.unwrap()),
+    };
+    match action {
+      None => {}
+      Some(state::Action::Exit(x)) => break x,
+      Some(state::Action::Respond(xs)) => {
+        for x in xs {
+          s_out.send(x).unwrap();
+        }
+      }
     }
   };
   drop(r_inc);
@@ -0,0 +1,62 @@
+//! A pool of threads for doing work in the background.
+
+use crossbeam_channel::Sender;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The stack size of each thread. Checking deeply nested code recurses deeply, so this is the same
+/// as the stack size of the thread the CLI checks on.
+const STACK_SIZE: usize = 10 * 1024 * 1024;
+
+/// A pool of threads.
+pub struct Pool {
+  sender: Sender<Job>,
+}
+
+impl Pool {
+  /// Returns a new Pool with a thread for each CPU.
+  pub fn new() -> Self {
+    let n = std::thread::available_parallelism().map_or(1, |x| x.get());
+    let (sender, receiver) = crossbeam_channel::unbounded::<Job>();
+    for i in 0..n {
+      let receiver = receiver.clone();
+      std::thread::Builder::new()
+        .name(format!("pool_{}", i))
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+          for job in receiver {
+            job();
+          }
+        })
+        .unwrap();
+    }
+    Self { sender }
+  }
+
+  /// Runs `f` on some thread in the pool.
+  pub fn spawn<F>(&self, f: F)
+  where
+    F: FnOnce() + Send + 'static,
+  {
+    self.sender.send(Box::new(f)).unwrap();
+  }
+}
+
+/// A token for cancelling some work in the pool. The work should check the token every so often,
+/// and stop if it was cancelled.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+  /// Cancels the work.
+  pub fn cancel(&self) {
+    self.0.store(true, Ordering::SeqCst);
+  }
+
+  /// Returns whether the work was cancelled.
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::SeqCst)
+  }
+}
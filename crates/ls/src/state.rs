@@ -1,155 +1,1477 @@
 //! The core of the server logic.
 
 use crate::comm::{
-  IncomingNotification, IncomingRequestParams, Outgoing, OutgoingNotification, Request, Response,
-  ResponseSuccess,
+  ErrorCode, Incoming, IncomingNotification, IncomingRequestParams, Outgoing, OutgoingNotification,
+  Request, Response, ResponseError, ResponseSuccess,
 };
+use crate::config::Config;
+use crate::log::{Level, Logger};
+use crate::pool::{CancelToken, Pool};
+use crate::symbols;
+use crossbeam_channel::Sender;
 use lsp_types::{
-  Diagnostic, InitializeResult, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
-  ServerInfo, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+  code_action_kind, CodeAction, CodeActionOrCommand, CodeActionParams,
+  CodeActionProviderCapability, CodeActionResponse, CompletionItem, CompletionItemKind,
+  CompletionOptions, Diagnostic, DiagnosticSeverity, DocumentSymbolResponse, Hover, HoverContents,
+  InitializeResult, LanguageString, Location, MarkedString, MessageType, NumberOrString, Position,
+  PublishDiagnosticsParams, Range, RenameProviderCapability, ServerCapabilities, ServerInfo,
+  ShowMessageParams, SymbolInformation, TextDocumentContentChangeEvent, TextDocumentPositionParams,
+  TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit,
 };
-use millet_core::intern::StrStoreMut;
-use millet_core::loc::Loc;
-use millet_core::{lex, parse, statics};
+use millet_core::ast::TopDec;
+use millet_core::intern::{StrRef, StrStore, StrStoreMut};
+use millet_core::line_index::{self, LineIndex};
+use millet_core::loc::{Loc, Located};
+use millet_core::project::mlb::Mlb;
+use millet_core::statics::{Lint, NameKind, Severity};
+use millet_core::token::{self, Token};
+use millet_core::{lex, parse, project, statics};
+use serde_json::Value;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 pub struct State {
   root_uri: Option<Url>,
+  /// The settings from the client.
+  config: Config,
+  got_initialize: bool,
   got_shutdown: bool,
+  /// The documents the client has open.
+  docs: HashMap<Url, Doc>,
+  /// The files we last published diagnostics for from checking the project, and the project file,
+  /// if it had an error.
+  project_uris: HashSet<Url>,
+  /// The generation of the check that last set `project_uris`.
+  project_generation: u64,
+  /// The number of times the documents have been opened, changed, or closed.
+  generation: u64,
+  /// The generation of the check that last published diagnostics for each file.
+  published: HashMap<Url, u64>,
+  /// The threads that check in the background.
+  pool: Pool,
+  /// Where the threads in the pool send what they did.
+  done: Sender<Done>,
+  /// Logs to the client, at the level from the settings.
+  log: Logger,
+  /// The requests being handled in the pool, with the document each is about, if any.
+  pending: HashMap<NumberOrString, (Option<Url>, CancelToken)>,
+  /// The checks for diagnostics being done in the pool, by the document that changed.
+  checking: HashMap<Url, CancelToken>,
+  /// The checks done since the documents or settings last changed, to reuse when handling requests.
+  checks: Checks,
 }
 
 impl State {
-  /// Returns a new State.
-  pub fn new() -> Self {
+  /// Returns a new State. Work done in the background will be sent to `done`, and should be passed
+  /// back to `handle_done`.
+  pub fn new(done: Sender<Done>) -> Self {
     Self {
       root_uri: None,
+      config: Config::default(),
+      got_initialize: false,
       got_shutdown: false,
+      docs: HashMap::new(),
+      project_uris: HashSet::new(),
+      project_generation: 0,
+      generation: 0,
+      published: HashMap::new(),
+      pool: Pool::new(),
+      log: Logger::new(Level::default(), done.clone()),
+      done,
+      pending: HashMap::new(),
+      checking: HashMap::new(),
+      checks: Checks::default(),
     }
   }
 
-  /// Returns the Response for this Request.
-  pub fn handle_request(&mut self, req: Request<IncomingRequestParams>) -> Response {
+  /// Handle an incoming message by possibly taking some action.
+  pub fn handle(&mut self, incoming: Incoming) -> Option<Action> {
+    match incoming {
+      Incoming::Request(req) => {
+        let res = self.handle_request(req)?;
+        Some(Action::Respond(vec![Outgoing::Response(res)]))
+      }
+      Incoming::Notification(notif) => self.handle_notification(notif),
+      Incoming::Invalid(res) => Some(Action::Respond(vec![Outgoing::Response(res)])),
+    }
+  }
+
+  /// Returns the Response for this Request, or `None` if the request is being handled in the
+  /// background.
+  fn handle_request(&mut self, req: Request<IncomingRequestParams>) -> Option<Response> {
+    if !self.got_initialize && !matches!(req.params, IncomingRequestParams::Initialize(_)) {
+      return Some(Response {
+        id: Some(req.id),
+        res: Err(ResponseError::new(
+          ErrorCode::ServerNotInitialized,
+          "server not initialized",
+        )),
+      });
+    }
     let res = match req.params {
       IncomingRequestParams::Initialize(params) => {
         // TODO do something with params.process_id
+        self.got_initialize = true;
         self.root_uri = params.root_uri;
-        Ok(ResponseSuccess::Initialize(InitializeResult {
+        self.set_config(
+          params
+            .initialization_options
+            .as_ref()
+            .unwrap_or(&Value::Null),
+        );
+        match &self.root_uri {
+          Some(uri) => self.log.log(Level::Info, format!("root: {}", uri)),
+          None => self.log.log(Level::Info, "no root"),
+        }
+        Ok(ResponseSuccess::Initialize(Box::new(InitializeResult {
           capabilities: ServerCapabilities {
-            text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::Full)),
+            text_document_sync: Some(TextDocumentSyncCapability::Kind(
+              TextDocumentSyncKind::Incremental,
+            )),
+            definition_provider: Some(true),
+            hover_provider: Some(true),
+            references_provider: Some(true),
+            rename_provider: Some(RenameProviderCapability::Simple(true)),
+            completion_provider: Some(CompletionOptions {
+              trigger_characters: Some(vec![".".to_owned()]),
+              ..CompletionOptions::default()
+            }),
+            document_symbol_provider: Some(true),
+            workspace_symbol_provider: Some(true),
+            code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
             ..ServerCapabilities::default()
           },
           server_info: Some(ServerInfo {
             name: "millet-ls".to_owned(),
             version: Some(env!("CARGO_PKG_VERSION").to_owned()),
           }),
-        }))
+        })))
       }
       IncomingRequestParams::Shutdown => {
         self.got_shutdown = true;
         Ok(ResponseSuccess::Null)
       }
+      IncomingRequestParams::Definition(params) => {
+        let params = params.text_document_position_params;
+        let uri = params.text_document.uri.clone();
+        self.spawn_request(req.id, Some(uri), move |snap| {
+          snap.definition(params).map(ResponseSuccess::Definition)
+        });
+        return None;
+      }
+      IncomingRequestParams::Hover(params) => {
+        let params = params.text_document_position_params;
+        let uri = params.text_document.uri.clone();
+        self.spawn_request(req.id, Some(uri), move |snap| {
+          snap.hover(params).map(ResponseSuccess::Hover)
+        });
+        return None;
+      }
+      IncomingRequestParams::References(params) => {
+        let include_declaration = params.context.include_declaration;
+        let params = params.text_document_position;
+        let uri = params.text_document.uri.clone();
+        self.spawn_request(req.id, Some(uri), move |snap| {
+          snap
+            .references(params, include_declaration)
+            .map(ResponseSuccess::References)
+        });
+        return None;
+      }
+      IncomingRequestParams::Completion(params) => {
+        let params = params.text_document_position;
+        let uri = params.text_document.uri.clone();
+        self.spawn_request(req.id, Some(uri), move |snap| {
+          snap.completion(params).map(ResponseSuccess::Completion)
+        });
+        return None;
+      }
+      IncomingRequestParams::DocumentSymbol(params) => {
+        let uri = params.text_document.uri;
+        self.spawn_request(req.id, Some(uri.clone()), move |snap| {
+          Ok(ResponseSuccess::DocumentSymbol(snap.document_symbol(&uri)))
+        });
+        return None;
+      }
+      IncomingRequestParams::WorkspaceSymbol(params) => {
+        self.spawn_request(req.id, None, move |snap| {
+          Ok(ResponseSuccess::WorkspaceSymbol(Some(
+            snap.workspace_symbol(&params.query),
+          )))
+        });
+        return None;
+      }
+      IncomingRequestParams::CodeAction(params) => {
+        let uri = params.text_document.uri.clone();
+        self.spawn_request(req.id, Some(uri), move |snap| {
+          snap.code_action(params).map(ResponseSuccess::CodeAction)
+        });
+        return None;
+      }
+      IncomingRequestParams::Rename(params) => {
+        let uri = params.text_document_position.text_document.uri.clone();
+        self.spawn_request(req.id, Some(uri), move |snap| {
+          snap
+            .rename(params.text_document_position, &params.new_name)
+            .map(ResponseSuccess::Rename)
+        });
+        return None;
+      }
     };
-    Response {
+    Some(Response {
       id: Some(req.id),
       res,
-    }
+    })
   }
 
-  /// Handle a notification by possibly taking some action.
-  pub fn handle_notification(&mut self, notif: IncomingNotification) -> Option<Action> {
+  /// Handle a notification by possibly taking some action. Notifications other than exit are
+  /// ignored before the initialize request.
+  fn handle_notification(&mut self, notif: IncomingNotification) -> Option<Action> {
+    if !self.got_initialize && !matches!(notif, IncomingNotification::Exit) {
+      return None;
+    }
     match notif {
       IncomingNotification::Initialized => None,
+      IncomingNotification::ChangeConfiguration(params) => {
+        self.set_config(&params.settings);
+        None
+      }
       IncomingNotification::Exit => Some(Action::Exit(self.got_shutdown)),
-      IncomingNotification::TextDocOpen(params) => Some(mk_diagnostic_action(
-        params.text_document.uri,
-        Some(params.text_document.version),
-        params.text_document.text.as_bytes(),
-      )),
-      IncomingNotification::TextDocChange(mut params) => {
-        assert_eq!(params.content_changes.len(), 1);
-        let change = params.content_changes.pop().unwrap();
-        assert!(change.range.is_none());
-        Some(mk_diagnostic_action(
-          params.text_document.uri,
-          params.text_document.version,
-          change.text.as_bytes(),
-        ))
+      IncomingNotification::Cancel(params) => {
+        let (_, token) = self.pending.remove(&params.id)?;
+        token.cancel();
+        let res = Response {
+          id: Some(params.id),
+          res: Err(ResponseError {
+            code: ErrorCode::RequestCancelled,
+            message: "request cancelled".to_owned(),
+          }),
+        };
+        Some(Action::Respond(vec![Outgoing::Response(res)]))
+      }
+      IncomingNotification::TextDocOpen(params) => {
+        let doc = params.text_document;
+        self.docs.insert(
+          doc.uri.clone(),
+          Doc {
+            version: Some(doc.version),
+            text: doc.text,
+          },
+        );
+        Some(self.changed(doc.uri))
+      }
+      IncomingNotification::TextDocChange(params) => {
+        let uri = params.text_document.uri;
+        let doc = self.docs.get_mut(&uri)?;
+        doc.version = params.text_document.version;
+        for change in params.content_changes {
+          apply_change(&mut doc.text, change);
+        }
+        Some(self.changed(uri))
+      }
+      IncomingNotification::TextDocSave(params) => {
+        // nothing changed, so just publish the diagnostics again, unless they're already coming.
+        let uri = params.text_document.uri;
+        if !self.checking.contains_key(&uri) {
+          self.spawn_diagnostics(uri);
+        }
+        None
+      }
+      IncomingNotification::TextDocClose(params) => {
+        self.docs.remove(&params.text_document.uri);
+        Some(self.changed(params.text_document.uri))
       }
-      IncomingNotification::TextDocSave(_) => None,
-      IncomingNotification::TextDocClose(_) => None,
     }
   }
+
+  /// Handle some work that was done in the background by possibly taking some action.
+  pub fn handle_done(&mut self, done: Done) -> Option<Action> {
+    match done {
+      Done::Notification(notif) => Some(Action::Respond(vec![Outgoing::Notification(notif)])),
+      Done::Response(res) => {
+        self.pending.remove(res.id.as_ref()?)?;
+        Some(Action::Respond(vec![Outgoing::Response(res)]))
+      }
+      Done::Diagnostics(diagnostics) => {
+        if diagnostics.token.is_cancelled() {
+          let msg = format!("dropping cancelled check for {}", diagnostics.uri);
+          self.log.log(Level::Log, msg);
+          return None;
+        }
+        self.checking.remove(&diagnostics.uri);
+        Some(self.diagnostic_action(*diagnostics))
+      }
+    }
+  }
+
+  /// Updates the settings with those in `val`. They are used in the checks started after this.
+  fn set_config(&mut self, val: &Value) {
+    let errors = self.config.update(val);
+    self.log.set_level(self.config.log_level);
+    for e in errors {
+      self
+        .log
+        .log(Level::Warning, format!("invalid settings: {}", e));
+    }
+    self.checks = Checks::default();
+  }
+
+  /// Returns whether there is no work being done in the background whose result we're waiting for.
+  #[cfg(test)]
+  pub fn is_idle(&self) -> bool {
+    self.pending.is_empty() && self.checking.is_empty()
+  }
+
+  /// Returns a snapshot of what's needed to do work in the background.
+  fn snapshot(&self) -> Snapshot {
+    Snapshot {
+      root_uri: self.root_uri.clone(),
+      config: self.config.clone(),
+      docs: self.docs.clone(),
+      token: CancelToken::default(),
+      log: self.log.clone(),
+      checks: self.checks.clone(),
+    }
+  }
+
+  /// Handles the request with `id` in the background by calling `f`. The request is about the
+  /// document at `uri`, if there is one.
+  fn spawn_request<F>(&mut self, id: NumberOrString, uri: Option<Url>, f: F)
+  where
+    F: FnOnce(&Snapshot) -> Result<ResponseSuccess, ResponseError> + Send + 'static,
+  {
+    let snap = self.snapshot();
+    self.pending.insert(id.clone(), (uri, snap.token.clone()));
+    let done = self.done.clone();
+    self.pool.spawn(move || {
+      if snap.token.is_cancelled() {
+        return;
+      }
+      let res = catch_unwind(AssertUnwindSafe(|| f(&snap))).unwrap_or_else(|e| {
+        let msg = format!("internal error: {}", panic_message(&*e));
+        snap.log.log(Level::Error, msg.as_str());
+        Err(ResponseError::new(ErrorCode::InternalError, msg))
+      });
+      let _ = done.send(Done::Response(Response { id: Some(id), res }));
+    });
+  }
+
+  /// Checks for diagnostics in the background, after the document at `uri` was opened, changed, or
+  /// closed. This cancels the requests about that document being handled in the background. Returns
+  /// an action to respond to the cancelled requests with `ContentModified`.
+  fn changed(&mut self, uri: Url) -> Action {
+    self.generation += 1;
+    self.checks = Checks::default();
+    let modified: Vec<_> = self
+      .pending
+      .iter()
+      .filter(|(_, (x, _))| x.as_ref() == Some(&uri))
+      .map(|(id, _)| id.clone())
+      .collect();
+    let mut ret = Vec::with_capacity(modified.len());
+    for id in modified {
+      let (_, token) = self.pending.remove(&id).unwrap();
+      token.cancel();
+      ret.push(Outgoing::Response(Response {
+        id: Some(id),
+        res: Err(ResponseError {
+          code: ErrorCode::ContentModified,
+          message: "content modified".to_owned(),
+        }),
+      }));
+    }
+    self.spawn_diagnostics(uri);
+    Action::Respond(ret)
+  }
+
+  /// Checks for diagnostics for the document at `uri` in the background. This cancels the older
+  /// check for diagnostics after it changed, if there is one.
+  fn spawn_diagnostics(&mut self, uri: Url) {
+    let snap = self.snapshot();
+    if let Some(token) = self.checking.insert(uri.clone(), snap.token.clone()) {
+      token.cancel();
+    }
+    let generation = self.generation;
+    let done = self.done.clone();
+    self.pool.spawn(move || {
+      if snap.token.is_cancelled() {
+        return;
+      }
+      let res = catch_unwind(AssertUnwindSafe(|| {
+        snap.diagnostics(uri.clone(), generation)
+      }));
+      let diagnostics = res.unwrap_or_else(|e| {
+        let msg = panic_message(&*e);
+        let log_msg = format!("internal error when checking {}: {}", uri, msg);
+        snap.log.log(Level::Error, log_msg.as_str());
+        let params = ShowMessageParams {
+          typ: MessageType::Error,
+          message: format!("millet-ls: {}", log_msg),
+        };
+        let notif = OutgoingNotification::ShowMessage(params);
+        let _ = done.send(Done::Notification(notif));
+        snap.internal_error(uri, generation, format!("internal error: {}", msg))
+      });
+      let _ = done.send(Done::Diagnostics(Box::new(diagnostics)));
+    });
+  }
+
+  /// Returns an action to publish the diagnostics. Diagnostics are published for the document that
+  /// changed and the files after it in the project, since the files before it can't have changed,
+  /// and cleared for files no longer in the project. Diagnostics for a file are not published if
+  /// they are older than the ones last published for it.
+  fn diagnostic_action(&mut self, diagnostics: Diagnostics) -> Action {
+    let mut ret = Vec::new();
+    let mut project_uris = HashSet::new();
+    let has_error = diagnostics.error.is_some();
+    if let Some((project_uri, diagnostic)) = diagnostics.error {
+      ret.push(publish_diagnostics(
+        project_uri.clone(),
+        None,
+        vec![diagnostic],
+      ));
+      project_uris.insert(project_uri);
+    }
+    let uri = diagnostics.uri;
+    let generation = diagnostics.generation;
+    let start = diagnostics.files.iter().position(|x| x.0 == uri).unwrap();
+    let iter = diagnostics.files.into_iter().enumerate();
+    for (file, (file_uri, version, file_diagnostics)) in iter {
+      let skip = (file < start && self.project_uris.contains(&file_uri))
+        || self
+          .published
+          .get(&file_uri)
+          .is_some_and(|&x| x > generation);
+      if diagnostics.project {
+        project_uris.insert(file_uri.clone());
+      }
+      if skip {
+        continue;
+      }
+      self.published.insert(file_uri.clone(), generation);
+      ret.push(publish_diagnostics(file_uri, version, file_diagnostics));
+    }
+    if (diagnostics.project || has_error) && generation >= self.project_generation {
+      let stale = self
+        .project_uris
+        .difference(&project_uris)
+        .filter(|&x| *x != uri);
+      ret.extend(stale.map(|x| publish_diagnostics(x.clone(), None, Vec::new())));
+      self.project_uris = project_uris;
+      self.project_generation = generation;
+    }
+    Action::Respond(ret)
+  }
+}
+
+/// Some work done in the background.
+pub enum Done {
+  /// A notification should be sent.
+  Notification(OutgoingNotification),
+  /// A request was handled.
+  Response(Response),
+  /// Some files were checked for diagnostics.
+  Diagnostics(Box<Diagnostics>),
+}
+
+/// The result of checking for diagnostics after a document was opened, changed, saved, or closed.
+pub struct Diagnostics {
+  /// The document.
+  uri: Url,
+  /// The generation of the State when the check started.
+  generation: u64,
+  /// The token for cancelling the check.
+  token: CancelToken,
+  /// The files checked, with their versions, if open, and their diagnostics.
+  files: Vec<(Url, Option<i64>, Vec<Diagnostic>)>,
+  /// Whether the files checked were the files of the project.
+  project: bool,
+  /// The project file and the error with it, if there was one.
+  error: Option<(Url, Diagnostic)>,
+}
+
+/// What's needed to do work in the background.
+struct Snapshot {
+  root_uri: Option<Url>,
+  config: Config,
+  /// The documents the client has open.
+  docs: HashMap<Url, Doc>,
+  /// The token for cancelling the work.
+  token: CancelToken,
+  log: Logger,
+  /// The checks done since the documents or settings last changed before this snapshot was taken.
+  checks: Checks,
+}
+
+impl Snapshot {
+  /// Checks for diagnostics after the document at `uri` was opened, changed, saved, or closed. See
+  /// `ck` for which files are checked. If the document was closed and is not in the project, its
+  /// diagnostics are empty.
+  fn diagnostics(&self, uri: Url, generation: u64) -> Diagnostics {
+    let mut ret = Diagnostics {
+      uri,
+      generation,
+      token: self.token.clone(),
+      files: Vec::new(),
+      project: false,
+      error: None,
+    };
+    let ck = match self.ck(&ret.uri, None) {
+      Ok(Some(x)) => x,
+      Ok(None) => {
+        ret.files.push((ret.uri.clone(), None, Vec::new()));
+        return ret;
+      }
+      Err(e) => return self.internal_error(ret.uri, generation, e.message),
+    };
+    if let Some(e) = &ck.error {
+      if let Ok(project_uri) = Url::from_file_path(e.path()) {
+        let diagnostic = Diagnostic {
+          severity: Some(DiagnosticSeverity::Error),
+          message: e.message(),
+          source: Some("millet-ls".to_owned()),
+          ..Diagnostic::default()
+        };
+        ret.error = Some((project_uri, diagnostic));
+      }
+    }
+    ret.project = ck.files.project;
+    let iter = ck.files.uris.iter().zip(ck.checked.diagnostics.iter());
+    ret.files = iter
+      .map(|(uri, diagnostics)| {
+        let version = self.docs.get(uri).and_then(|x| x.version);
+        let mut diagnostics = diagnostics.clone();
+        if let Some(n) = self.config.max_diagnostics {
+          diagnostics.truncate(n);
+        }
+        (uri.clone(), version, diagnostics)
+      })
+      .collect();
+    ret
+  }
+
+  /// Returns the diagnostics for when checking after the document at `uri` changed panicked with
+  /// `message`. Only the document gets a diagnostic, at its start.
+  fn internal_error(&self, uri: Url, generation: u64, message: String) -> Diagnostics {
+    let version = self.docs.get(&uri).and_then(|x| x.version);
+    let diagnostic = Diagnostic {
+      severity: Some(DiagnosticSeverity::Error),
+      message,
+      source: Some("millet-ls".to_owned()),
+      ..Diagnostic::default()
+    };
+    Diagnostics {
+      uri: uri.clone(),
+      generation,
+      token: self.token.clone(),
+      files: vec![(uri, version, vec![diagnostic])],
+      project: false,
+      error: None,
+    }
+  }
+
+  /// Returns the location of the definition of the name at the position, if there is one.
+  fn definition(
+    &self,
+    params: TextDocumentPositionParams,
+  ) -> Result<Option<Location>, ResponseError> {
+    let uri = params.text_document.uri;
+    let position = params.position;
+    Ok(self.ck(&uri, None)?.and_then(|ck| {
+      let file = ck.file(&uri)?;
+      let idx = byte_idx(&ck.files.lines[file], position);
+      let def = ck.checked.statics.definition(file, idx)?;
+      Some(ck.files.location(def.file, def.loc))
+    }))
+  }
+
+  /// Returns the locations of the uses of the name at the position, and its definition if
+  /// `include_declaration`, if there is such a name.
+  fn references(
+    &self,
+    params: TextDocumentPositionParams,
+    include_declaration: bool,
+  ) -> Result<Option<Vec<Location>>, ResponseError> {
+    let uri = params.text_document.uri;
+    let position = params.position;
+    Ok(self.ck(&uri, None)?.and_then(|ck| {
+      let file = ck.file(&uri)?;
+      let idx = byte_idx(&ck.files.lines[file], position);
+      let (def, uses) = ck.checked.statics.references(file, idx)?;
+      let def = if include_declaration {
+        Some((def.file, def.loc))
+      } else {
+        None
+      };
+      let ret = def
+        .into_iter()
+        .chain(uses)
+        .map(|(file, loc)| ck.files.location(file, loc))
+        .collect();
+      Some(ret)
+    }))
+  }
+
+  /// Returns the edits to rename the name at the position, and every other occurrence of it, to
+  /// `new_name`, if there is such a name. Returns an error if `new_name` is not an identifier, or
+  /// if renaming would change what some name refers to, or make one name shadow another in the
+  /// same environment.
+  fn rename(
+    &self,
+    params: TextDocumentPositionParams,
+    new_name: &str,
+  ) -> Result<Option<WorkspaceEdit>, ResponseError> {
+    let uri = params.text_document.uri;
+    let text = match self.docs.get(&uri) {
+      Some(x) => &x.text,
+      None => return Ok(None),
+    };
+    if !is_ident(new_name) {
+      return Err(ResponseError {
+        code: ErrorCode::InvalidParams,
+        message: format!("not an identifier: {}", new_name),
+      });
+    }
+    let idx = byte_idx(&LineIndex::new(text), params.position);
+    let ck = match self.ck(&uri, None)? {
+      Some(x) => x,
+      None => return Ok(None),
+    };
+    let checked = &ck.checked;
+    let mut files = ck.files.clone();
+    let (def, uses) = match ck.file(&uri) {
+      Some(file) => match checked.statics.references(file, idx) {
+        Some(x) => x,
+        None => return Ok(None),
+      },
+      None => return Ok(None),
+    };
+    let collision = || ResponseError {
+      code: ErrorCode::InvalidRequest,
+      message: format!(
+        "renaming to `{}` would collide with an existing name",
+        new_name
+      ),
+    };
+    if checked.statics.collides(&checked.store, def, new_name) {
+      return Err(collision());
+    }
+    let mut edits: Vec<Vec<Loc>> = vec![Vec::new(); files.uris.len()];
+    edits[def.file].push(def.loc);
+    for (file, loc) in uses {
+      edits[file].push(loc);
+    }
+    for locs in edits.iter_mut() {
+      locs.sort_unstable();
+      locs.dedup();
+    }
+    // to check that the renaming didn't change the meaning of the program, check it again after
+    // renaming, and make sure every name refers to the same thing it did before.
+    let old_len = std::ops::Range::<usize>::from(def.loc).len();
+    let shift = |file: usize, loc: Loc| {
+      let map = |idx: usize| {
+        let before = edits[file]
+          .iter()
+          .filter(|&&x| std::ops::Range::<usize>::from(x).start < idx)
+          .count();
+        idx + before * new_name.len() - before * old_len
+      };
+      let range: std::ops::Range<usize> = loc.into();
+      Loc::new(map(range.start), map(range.end))
+    };
+    let mut want: Vec<_> = checked
+      .statics
+      .uses()
+      .iter()
+      .map(|&(file, loc, d)| (file, shift(file, loc), d.file, shift(d.file, d.loc)))
+      .collect();
+    let num_errors: usize = checked.diagnostics.iter().map(Vec::len).sum();
+    let mut changes = HashMap::new();
+    for (file, locs) in edits.iter().enumerate() {
+      if locs.is_empty() {
+        continue;
+      }
+      let text_edits = locs
+        .iter()
+        .map(|&loc| TextEdit {
+          range: range(&files.lines[file], loc),
+          new_text: new_name.to_owned(),
+        })
+        .collect();
+      changes.insert(files.uris[file].clone(), text_edits);
+      let mut new_contents = String::with_capacity(files.contents[file].len());
+      let mut prev = 0;
+      for &loc in locs {
+        let range: std::ops::Range<usize> = loc.into();
+        new_contents.push_str(&files.contents[file][prev..range.start]);
+        new_contents.push_str(new_name);
+        prev = range.end;
+      }
+      new_contents.push_str(&files.contents[file][prev..]);
+      files.lines[file] = LineIndex::new(&new_contents);
+      files.contents[file] = new_contents;
+    }
+    let new_checked = ck_files(&files, None, &self.config, &self.token).map_err(|_| collision())?;
+    let mut got: Vec<_> = new_checked
+      .statics
+      .uses()
+      .iter()
+      .map(|&(file, loc, d)| (file, loc, d.file, d.loc))
+      .collect();
+    want.sort_unstable();
+    want.dedup();
+    got.sort_unstable();
+    got.dedup();
+    let new_num_errors: usize = new_checked.diagnostics.iter().map(Vec::len).sum();
+    if want != got || new_num_errors > num_errors {
+      return Err(collision());
+    }
+    Ok(Some(WorkspaceEdit {
+      changes: Some(changes),
+      ..WorkspaceEdit::default()
+    }))
+  }
+
+  /// Returns the type of the expression or pattern at the position, if there is one.
+  fn hover(&self, params: TextDocumentPositionParams) -> Result<Option<Hover>, ResponseError> {
+    let uri = params.text_document.uri;
+    let position = params.position;
+    Ok(self.ck(&uri, None)?.and_then(|ck| {
+      let file = ck.file(&uri)?;
+      let lines = &ck.files.lines[file];
+      let idx = byte_idx(lines, position);
+      let (loc, ty) = ck.checked.statics.ty_at(&ck.checked.store, file, idx)?;
+      Some(Hover {
+        contents: HoverContents::Scalar(MarkedString::LanguageString(LanguageString {
+          language: "sml".to_owned(),
+          value: ty,
+        })),
+        range: Some(range(lines, loc)),
+      })
+    }))
+  }
+
+  /// Returns the completions at the position. After a structure name and a `.`, these are the names
+  /// in that structure. Otherwise, they are the names in scope there, along with reserved words.
+  fn completion(
+    &self,
+    params: TextDocumentPositionParams,
+  ) -> Result<Option<Vec<CompletionItem>>, ResponseError> {
+    let uri = params.text_document.uri;
+    let text = match self.docs.get(&uri) {
+      Some(x) => &x.text,
+      None => return Ok(None),
+    };
+    let idx = byte_idx(&LineIndex::new(text), params.position);
+    let path = structure_path(&text[..idx]);
+    let ck = match self.ck(&uri, Some(idx))? {
+      Some(x) => x,
+      None => return Ok(None),
+    };
+    let checked = &ck.checked;
+    let (names, in_exp) = match checked.statics.names_in_scope(&checked.store, &path) {
+      Some(x) => x,
+      None => return Ok(None),
+    };
+    let mut ret: Vec<_> = names
+      .into_iter()
+      .map(|name| CompletionItem {
+        label: name.name,
+        kind: Some(match name.kind {
+          NameKind::Structure => CompletionItemKind::Module,
+          NameKind::Type => CompletionItemKind::Class,
+          NameKind::Constructor => CompletionItemKind::EnumMember,
+          NameKind::Exception => CompletionItemKind::Constructor,
+          NameKind::Value => CompletionItemKind::Value,
+        }),
+        detail: name.detail,
+        ..CompletionItem::default()
+      })
+      .collect();
+    if path.is_empty() {
+      let keywords = token::ALPHA
+        .iter()
+        .filter(|(_, tok)| {
+          if in_exp {
+            tok.in_exp()
+          } else {
+            tok.begins_dec()
+          }
+        })
+        .map(|&(bs, _)| CompletionItem {
+          label: std::str::from_utf8(bs).unwrap().to_owned(),
+          kind: Some(CompletionItemKind::Keyword),
+          ..CompletionItem::default()
+        });
+      ret.extend(keywords);
+    }
+    Ok(Some(ret))
+  }
+
+  /// Returns an outline of the declarations in the document at `uri`, if we have that document.
+  fn document_symbol(&self, uri: &Url) -> Option<DocumentSymbolResponse> {
+    let text = &self.docs.get(uri)?.text;
+    let files = Files::new(vec![uri.clone()], vec![text.clone()], None);
+    let (store, top_decs, _) = parse_files(&files);
+    let ret = symbols::get(&store, &files.lines[0], &top_decs[0]);
+    Some(DocumentSymbolResponse::Nested(ret))
+  }
+
+  /// Returns the declarations whose names contain `query`, ignoring case, in every file of the
+  /// project described by the project file at the workspace root. If there is no such project, the
+  /// declarations are those in the documents we've been sent.
+  fn workspace_symbol(&self, query: &str) -> Vec<SymbolInformation> {
+    let files = self.workspace_files();
+    let (store, top_decs, _) = parse_files(&files);
+    let query = query.to_lowercase();
+    let mut ret = Vec::new();
+    let iter = files.uris.iter().zip(files.lines.iter()).zip(top_decs);
+    for ((uri, lines), top_decs) in iter {
+      let mut stack: Vec<_> = symbols::get(&store, lines, &top_decs)
+        .into_iter()
+        .rev()
+        .map(|x| (x, None))
+        .collect();
+      while let Some((mut sym, container_name)) = stack.pop() {
+        let children = sym.children.take().unwrap_or_default();
+        stack.extend(
+          children
+            .into_iter()
+            .rev()
+            .map(|x| (x, Some(sym.name.clone()))),
+        );
+        if !sym.name.to_lowercase().contains(&query) {
+          continue;
+        }
+        ret.push(SymbolInformation {
+          name: sym.name,
+          kind: sym.kind,
+          deprecated: None,
+          location: Location {
+            uri: uri.clone(),
+            range: sym.selection_range,
+          },
+          container_name,
+        });
+      }
+    }
+    ret
+  }
+
+  /// Returns the quick fix to add the arms missing from the smallest non-exhaustive match containing
+  /// the start of the range, if there is such a match. The arms are added after the last arm, lined
+  /// up with it, and raise an exception.
+  fn code_action(
+    &self,
+    params: CodeActionParams,
+  ) -> Result<Option<CodeActionResponse>, ResponseError> {
+    let uri = params.text_document.uri;
+    let ck = match self.ck(&uri, None)? {
+      Some(x) => x,
+      None => return Ok(None),
+    };
+    let (files, checked) = (&ck.files, &ck.checked);
+    let file = match ck.file(&uri) {
+      Some(x) => x,
+      None => return Ok(None),
+    };
+    let contents = &files.contents[file];
+    let lines = &files.lines[file];
+    let idx = byte_idx(lines, params.range.start);
+    let arms = match checked.statics.missing_arms(&checked.store, file, idx) {
+      Some(x) => x,
+      None => return Ok(None),
+    };
+    // line up the new arms' `|` with the last arm's, or if it has none, put them 2 columns before
+    // the last arm.
+    let last_arm = std::ops::Range::<usize>::from(arms.last_arm).start;
+    let line_start = contents[..last_arm].rfind('\n').map_or(0, |x| x + 1);
+    let before = contents[line_start..last_arm].trim_end();
+    let mut indent: Vec<_> = if let Some(before) = before.strip_suffix('|') {
+      before.chars().collect()
+    } else {
+      let mut ret: Vec<_> = contents[line_start..last_arm].chars().collect();
+      ret.truncate(ret.len().saturating_sub(2));
+      ret
+    };
+    for c in indent.iter_mut() {
+      if *c != '\t' {
+        *c = ' ';
+      }
+    }
+    let indent: String = indent.into_iter().collect();
+    let arrow = if arms.is_fun { "=" } else { "=>" };
+    let mut new_text = String::new();
+    for pat in arms.pats.iter() {
+      new_text.push('\n');
+      new_text.push_str(&indent);
+      new_text.push_str(&format!("| {} {} raise Fail \"unimplemented\"", pat, arrow));
+    }
+    let end = position(lines, std::ops::Range::<usize>::from(arms.loc).end);
+    let edit = TextEdit {
+      range: Range { start: end, end },
+      new_text,
+    };
+    let loc_range = range(lines, arms.loc);
+    let diagnostics: Vec<_> = params
+      .context
+      .diagnostics
+      .into_iter()
+      .filter(|x| x.range == loc_range && x.message.starts_with("non-exhaustive match"))
+      .collect();
+    let title = if arms.pats.len() == 1 {
+      "Add missing arm"
+    } else {
+      "Add missing arms"
+    };
+    let action = CodeAction {
+      title: title.to_owned(),
+      kind: Some(code_action_kind::QUICKFIX.to_owned()),
+      diagnostics: Some(diagnostics),
+      edit: Some(WorkspaceEdit {
+        changes: Some(vec![(uri, vec![edit])].into_iter().collect()),
+        ..WorkspaceEdit::default()
+      }),
+      command: None,
+      is_preferred: None,
+    };
+    Ok(Some(vec![CodeActionOrCommand::CodeAction(action)]))
+  }
+
+  /// Returns the files in the project described by the project file at the workspace root. If there
+  /// is no such project, or it has errors, returns the open documents.
+  fn workspace_files(&self) -> Files {
+    let proj = self
+      .project_file()
+      .and_then(|x| project::get(&x, |p| self.read(p)).ok());
+    let mut uris = Vec::new();
+    let mut contents = Vec::new();
+    match proj {
+      None => {
+        for (uri, doc) in self.docs.iter() {
+          uris.push(uri.clone());
+          contents.push(doc.text.clone());
+        }
+      }
+      Some(proj) => {
+        for p in proj.files {
+          let text = match self.read(&p) {
+            Ok(x) => x,
+            Err(_) => continue,
+          };
+          // the project file path was absolute, since it came from a URL, so every path in it is
+          // too.
+          uris.push(Url::from_file_path(&p).unwrap());
+          contents.push(text);
+        }
+      }
+    }
+    Files::new(uris, contents, None)
+  }
+
+  /// Checks the document at `uri`, as `ck_uncached` does. If `scope_at` is `None`, a check of the
+  /// document done since the documents or settings last changed is reused, if there is one.
+  fn ck(&self, uri: &Url, scope_at: Option<usize>) -> Result<Option<Arc<Ck>>, ResponseError> {
+    // what's in scope is only recorded when checking, so those checks can't be reused.
+    if scope_at.is_none() {
+      let checks = self.checks.lock().unwrap();
+      if let Some(ck) = checks.iter().find(|ck| ck.file(uri).is_some()) {
+        return Ok(Some(ck.clone()));
+      }
+    }
+    let ret = match self.ck_uncached(uri, scope_at)? {
+      Some(x) => Arc::new(x),
+      None => return Ok(None),
+    };
+    // if the check was cancelled, it may have stopped before checking every file.
+    if scope_at.is_none() && !self.token.is_cancelled() {
+      self.checks.lock().unwrap().push(ret.clone());
+    }
+    Ok(Some(ret))
+  }
+
+  /// Checks the document at `uri`. If the document is part of the project described by the project
+  /// file at the workspace root, the whole project is checked. Otherwise, the document is checked on
+  /// its own, if it is open. If `scope_at` is a byte index in the document, what is in scope there
+  /// is recorded in the statics. Returns an error if the document is in the project, but is neither
+  /// open nor has the URI we get from its path.
+  fn ck_uncached(&self, uri: &Url, scope_at: Option<usize>) -> Result<Option<Ck>, ResponseError> {
+    let mut error = None;
+    let start = Instant::now();
+    if let Some(project_file) = self.project_file() {
+      let res = match self.project_files(&project_file, uri) {
+        Ok(Some(files)) => {
+          let file = match files.uris.iter().position(|x| x == uri) {
+            Some(x) => x,
+            None => {
+              let msg = format!(
+                "cannot find {} in the project {}",
+                uri,
+                project_file.display()
+              );
+              return Err(ResponseError::new(ErrorCode::InternalError, msg));
+            }
+          };
+          let scope_at = scope_at.map(|idx| (file, idx));
+          ck_files(&files, scope_at, &self.config, &self.token).map(|x| Some((files, x)))
+        }
+        Ok(None) => Ok(None),
+        Err(e) => Err(e),
+      };
+      match res {
+        Ok(Some((files, checked))) => {
+          let msg = format!(
+            "checked {} files in the project {} in {:?}",
+            files.uris.len(),
+            project_file.display(),
+            start.elapsed()
+          );
+          self.log.log(Level::Log, msg);
+          return Ok(Some(Ck {
+            files,
+            checked,
+            error: None,
+          }));
+        }
+        Ok(None) => {
+          let msg = format!("{} is not in the project {}", uri, project_file.display());
+          self.log.log(Level::Info, msg);
+        }
+        Err(e) => {
+          self.log.log(Level::Warning, e.message());
+          error = Some(e);
+        }
+      }
+    }
+    let text = match self.docs.get(uri) {
+      Some(x) => &x.text,
+      None => return Ok(None),
+    };
+    let files = Files::new(vec![uri.clone()], vec![text.clone()], None);
+    let checked = ck_files(
+      &files,
+      scope_at.map(|idx| (0, idx)),
+      &self.config,
+      &self.token,
+    )
+    .expect("only MLB projects have errors when checking");
+    self.log.log(
+      Level::Log,
+      format!("checked {} in {:?}", uri, start.elapsed()),
+    );
+    Ok(Some(Ck {
+      files,
+      checked,
+      error,
+    }))
+  }
+
+  /// Returns the project file from the settings, or else the one at the workspace root (see
+  /// `project::find`), if there is one.
+  fn project_file(&self) -> Option<PathBuf> {
+    let root = self.root_uri.as_ref()?.to_file_path().ok()?;
+    match &self.config.manifest_path {
+      Some(path) => Some(root.join(path)),
+      None => project::find(&root),
+    }
+  }
+
+  /// Returns the files in the project described by the project file. Returns `None` if the document
+  /// at `uri` is not in the project.
+  fn project_files(&self, project_file: &Path, uri: &Url) -> Result<Option<Files>, project::Error> {
+    let proj = project::get(project_file, |p| self.read(p))?;
+    for e in proj.warnings.iter() {
+      self.log.log(Level::Warning, e.message());
+    }
+    let path = match uri.to_file_path() {
+      Ok(x) => x,
+      Err(()) => return Ok(None),
+    };
+    if !proj.files.contains(&path) {
+      return Ok(None);
+    }
+    let mut uris = Vec::with_capacity(proj.files.len());
+    let mut contents = Vec::with_capacity(proj.files.len());
+    for p in proj.files.iter() {
+      contents.push(self.read(p).map_err(|e| project::Error::Io(p.clone(), e))?);
+      // use the URI the client used for the file if it's open, since the client may have encoded it
+      // differently than we would. else, the project file path was absolute, since it came from a
+      // URL, so every path in it is too.
+      let uri = match self.doc_at(p) {
+        Some((uri, _)) => uri.clone(),
+        None => Url::from_file_path(p).unwrap(),
+      };
+      uris.push(uri);
+    }
+    let paths = proj.files;
+    let mlb = proj.mlb.map(|x| (x, paths));
+    let mut ret = Files::new(uris, contents, mlb);
+    ret.project = true;
+    Ok(Some(ret))
+  }
+
+  /// Returns the contents of the file at `path`. If the file is open, these are the contents of the
+  /// open document, else they are read from disk.
+  fn read(&self, path: &Path) -> std::io::Result<String> {
+    if let Some((_, doc)) = self.doc_at(path) {
+      return Ok(doc.text.clone());
+    }
+    std::fs::read_to_string(path)
+  }
+
+  /// Returns the open document for the file at `path`, and its URI, if there is one. This compares
+  /// paths, not URIs, since the same path may be encoded as different URIs, like with or without a
+  /// percent-encoded drive letter or space.
+  fn doc_at(&self, path: &Path) -> Option<(&Url, &Doc)> {
+    self
+      .docs
+      .iter()
+      .find(|(uri, _)| uri.to_file_path().is_ok_and(|x| x == path))
+  }
+}
+
+/// A document the client has open.
+#[derive(Clone)]
+struct Doc {
+  /// The version, which increases after each change.
+  version: Option<i64>,
+  /// The contents.
+  text: String,
 }
 
 /// An action to take in response to a notification.
 pub enum Action {
   /// Exit the server. The bool is whether the process should exit successfully.
   Exit(bool),
-  /// Respond with an outgoing message.
-  Respond(Box<Outgoing>),
-}
-
-fn mk_diagnostic_action(uri: Url, version: Option<i64>, bs: &[u8]) -> Action {
-  let diagnostics: Vec<_> = ck_one_file(bs).into_iter().collect();
-  Action::Respond(
-    Outgoing::Notification(OutgoingNotification::PublishDiagnostics(
-      PublishDiagnosticsParams {
-        uri,
-        version,
-        diagnostics,
-      },
-    ))
-    .into(),
-  )
+  /// Respond with some outgoing messages.
+  Respond(Vec<Outgoing>),
+}
+
+/// Returns the message of the panic whose payload is `e`.
+fn panic_message(e: &(dyn Any + Send)) -> &str {
+  if let Some(&x) = e.downcast_ref::<&str>() {
+    x
+  } else if let Some(x) = e.downcast_ref::<String>() {
+    x
+  } else {
+    "unknown panic"
+  }
+}
+
+fn publish_diagnostics(uri: Url, version: Option<i64>, diagnostics: Vec<Diagnostic>) -> Outgoing {
+  Outgoing::Notification(OutgoingNotification::PublishDiagnostics(
+    PublishDiagnosticsParams {
+      uri,
+      version,
+      diagnostics,
+    },
+  ))
+}
+
+/// Some files to check together.
+#[derive(Clone)]
+struct Files {
+  uris: Vec<Url>,
+  contents: Vec<String>,
+  /// If this is `None`, the files are checked in order, with declarations in earlier files in scope
+  /// in later ones. Else the files are checked as the MLB files describe, and the paths are those
+  /// of the files.
+  mlb: Option<(Mlb, Vec<PathBuf>)>,
+  /// The lines of each file.
+  lines: Vec<LineIndex>,
+  /// Whether these are the files of the project, rather than one document on its own.
+  project: bool,
+}
+
+impl Files {
+  fn new(uris: Vec<Url>, contents: Vec<String>, mlb: Option<(Mlb, Vec<PathBuf>)>) -> Self {
+    let lines = contents.iter().map(|x| LineIndex::new(x)).collect();
+    Self {
+      uris,
+      contents,
+      mlb,
+      lines,
+      project: false,
+    }
+  }
+
+  fn location(&self, file: usize, loc: Loc) -> Location {
+    Location {
+      uri: self.uris[file].clone(),
+      range: range(&self.lines[file], loc),
+    }
+  }
+}
+
+/// The result of checking the document at a URI.
+struct Ck {
+  /// The files checked, which include the document.
+  files: Files,
+  /// The result of checking them.
+  checked: Checked,
+  /// The error with the project, if there was one.
+  error: Option<project::Error>,
+}
+
+impl Ck {
+  /// Returns the index of the document at `uri` in the files checked, if it is one of them.
+  fn file(&self, uri: &Url) -> Option<usize> {
+    self.files.uris.iter().position(|x| x == uri)
+  }
+}
+
+/// The checks done since the documents or settings last changed.
+type Checks = Arc<Mutex<Vec<Arc<Ck>>>>;
+
+/// The result of checking some files.
+struct Checked {
+  /// The diagnostics for each file.
+  diagnostics: Vec<Vec<Diagnostic>>,
+  /// The statics after checking every file.
+  statics: statics::Statics,
+  /// The strings in the files.
+  store: StrStore,
+}
+
+/// Checks the files. If `scope_at` is a file index and a byte index in that file, what is in scope
+/// there is recorded in the statics. If the token is cancelled, the rest of the files are not
+/// checked.
+fn ck_files(
+  files: &Files,
+  scope_at: Option<(usize, usize)>,
+  config: &Config,
+  token: &CancelToken,
+) -> Result<Checked, project::Error> {
+  // lets the tests check what happens when checking panics.
+  #[cfg(test)]
+  if files
+    .contents
+    .iter()
+    .any(|x| x.contains(crate::tests::PANIC))
+  {
+    panic!("asked to panic");
+  }
+  let (store, top_decs, mut ret) = parse_files(files);
+  let mut s = statics::Statics::new();
+  if let Some((file, idx)) = scope_at {
+    s.set_scope_at(file, idx);
+  }
+  match &files.mlb {
+    None => {
+      let iter = files.lines.iter().zip(ret.iter_mut()).zip(top_decs);
+      for (file, ((lines, diagnostics), xs)) in iter.enumerate() {
+        if token.is_cancelled() {
+          break;
+        }
+        for x in xs {
+          let errors = s.get(file, &x);
+          diagnostics.extend(statics_diagnostics(lines, &store, config, errors));
+        }
+      }
+    }
+    Some((mlb, paths)) => {
+      let std_basis = s.basis();
+      mlb.ck(&std_basis, &store, |path, basis| {
+        if token.is_cancelled() {
+          return statics::Basis::default();
+        }
+        let idx = paths
+          .iter()
+          .position(|x| x == path)
+          .expect("MLB files are in the project");
+        let (new, errors) = s.get_in(basis, idx, &top_decs[idx]);
+        let lines = &files.lines[idx];
+        ret[idx].extend(statics_diagnostics(lines, &store, config, errors));
+        new
+      })?;
+    }
+  }
+  Ok(Checked {
+    diagnostics: ret,
+    statics: s,
+    store,
+  })
 }
 
-fn ck_one_file(bs: &[u8]) -> Option<Diagnostic> {
+/// Returns the diagnostics for the errors from the statics, without the lints turned off in the
+/// settings.
+fn statics_diagnostics<'a>(
+  lines: &'a LineIndex,
+  store: &'a StrStore,
+  config: &'a Config,
+  errors: Vec<Located<statics::Error>>,
+) -> impl Iterator<Item = Diagnostic> + 'a {
+  errors.into_iter().filter_map(move |e| {
+    let lint = e.val.lint();
+    if lint.is_some_and(|x| !config.lint_enabled(x)) {
+      return None;
+    }
+    let mut severity = e.val.severity();
+    if let Some(Lint::NonExhaustiveMatch) | Some(Lint::NonExhaustiveBinding) = lint {
+      severity = config.non_exhaustive.unwrap_or(severity);
+    }
+    let mut ret = mk_diagnostic(lines, e.loc, e.val.message(store));
+    ret.severity = Some(match severity {
+      Severity::Error => DiagnosticSeverity::Error,
+      Severity::Warning => DiagnosticSeverity::Warning,
+    });
+    Some(ret)
+  })
+}
+
+/// The top-level declarations of a file.
+type TopDecs = Vec<Located<TopDec<StrRef>>>;
+
+/// Lexes and parses the files. Returns the strings in the files, the top-level declarations of each
+/// file, and the diagnostics for each file.
+fn parse_files(files: &Files) -> (StrStore, Vec<TopDecs>, Vec<Vec<Diagnostic>>) {
+  let mut ret: Vec<Vec<Diagnostic>> = vec![Vec::new(); files.contents.len()];
   let mut store = StrStoreMut::new();
-  let lexer = match lex::get(&mut store, bs) {
-    Ok(x) => x,
-    Err(e) => return Some(mk_diagnostic(bs, e.loc, e.val.message())),
-  };
+  let mut lexers = Vec::with_capacity(files.contents.len());
+  let iter = files.contents.iter().zip(files.lines.iter());
+  for ((contents, lines), diagnostics) in iter.clone().zip(ret.iter_mut()) {
+    match lex::get(&mut store, contents.as_bytes()) {
+      Ok(x) => lexers.push(Some(x)),
+      Err(e) => {
+        diagnostics.push(mk_diagnostic(lines, e.loc, e.val.message()));
+        lexers.push(None);
+      }
+    }
+  }
   let store = store.finish();
-  let top_decs = match parse::get(lexer) {
-    Ok(x) => x,
-    Err(e) => return Some(mk_diagnostic(bs, e.loc, e.val.message(&store))),
-  };
-  let mut s = statics::Statics::new();
-  for top_dec in top_decs {
-    match s.get(&top_dec) {
-      Ok(()) => {}
-      Err(e) => return Some(mk_diagnostic(bs, e.loc, e.val.message(&store))),
+  let mut top_decs = Vec::with_capacity(files.contents.len());
+  for (lines, (diagnostics, lexer)) in files.lines.iter().zip(ret.iter_mut().zip(lexers)) {
+    let lexer = match lexer {
+      Some(x) => x,
+      None => {
+        top_decs.push(Vec::new());
+        continue;
+      }
+    };
+    let (xs, errors) = parse::get(lexer);
+    for e in errors {
+      diagnostics.push(mk_diagnostic(lines, e.loc, e.val.message(&store)));
     }
+    top_decs.push(xs);
   }
-  None
+  (store, top_decs, ret)
 }
 
-fn mk_diagnostic(bs: &[u8], loc: Loc, message: String) -> Diagnostic {
-  let range: std::ops::Range<usize> = loc.into();
-  let range = Range {
-    start: position(bs, range.start),
-    end: position(bs, range.end),
-  };
+fn mk_diagnostic(lines: &LineIndex, loc: Loc, message: String) -> Diagnostic {
   Diagnostic {
-    range,
+    range: range(lines, loc),
+    severity: Some(DiagnosticSeverity::Error),
     message,
     source: Some("millet-ls".to_owned()),
     ..Diagnostic::default()
   }
 }
 
-fn position(bs: &[u8], byte_idx: usize) -> Position {
-  let mut line = 0;
-  let mut character = 0;
-  for (idx, &b) in bs.iter().enumerate() {
-    if idx == byte_idx {
-      break;
+pub fn range(lines: &LineIndex, loc: Loc) -> Range {
+  let range: std::ops::Range<usize> = loc.into();
+  Range {
+    start: position(lines, range.start),
+    end: position(lines, range.end),
+  }
+}
+
+fn position(lines: &LineIndex, byte_idx: usize) -> Position {
+  let pos = lines.position(byte_idx);
+  Position {
+    line: pos.line as u64,
+    character: pos.character as u64,
+  }
+}
+
+/// The inverse of `position`.
+fn byte_idx(lines: &LineIndex, pos: Position) -> usize {
+  lines.byte_idx(line_index::Position {
+    line: pos.line as usize,
+    character: pos.character as usize,
+  })
+}
+
+/// Applies the change to `text`. If the change has a range, the characters of its positions are in
+/// UTF-16 code units, as in every LSP position. Else the change replaces all of `text`.
+fn apply_change(text: &mut String, change: TextDocumentContentChangeEvent) {
+  match change.range {
+    None => *text = change.text,
+    Some(range) => {
+      let lines = LineIndex::new(text);
+      let start = byte_idx(&lines, range.start);
+      let end = byte_idx(&lines, range.end).max(start);
+      text.replace_range(start..end, &change.text);
     }
-    if b == b'\n' {
-      line += 1;
-      character = 0;
-    } else {
-      character += 1;
+  }
+}
+
+/// Returns whether `s` is a single identifier.
+fn is_ident(s: &str) -> bool {
+  let mut store = StrStoreMut::new();
+  match lex::get(&mut store, s.as_bytes()) {
+    Ok(lexer) => {
+      matches!(lexer.get(0), Some(tok) if matches!(tok.val, Token::Ident(..)))
+        && lexer.get(1).is_none()
+    }
+    Err(_) => false,
+  }
+}
+
+/// Returns the names of the structures in the qualified name being written at the end of `text`,
+/// like `["Foo", "Bar"]` for `Foo.Bar.b`. This is empty if the name being written is unqualified.
+fn structure_path(text: &str) -> Vec<&str> {
+  let is_alpha_num = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '\'';
+  let mut ret = Vec::new();
+  let mut rest = text.trim_end_matches(is_alpha_num);
+  while let Some(before) = rest.strip_suffix('.') {
+    let name = before.trim_end_matches(is_alpha_num);
+    let name = &before[name.len()..];
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+      break;
     }
+    ret.push(name);
+    rest = &before[..before.len() - name.len()];
   }
-  Position { line, character }
+  ret.reverse();
+  ret
+}
+
+#[test]
+fn test_apply_change() {
+  let change = |text: &str, start: (u64, u64), end: (u64, u64), new: &str| {
+    let mut text = text.to_owned();
+    let range = Range {
+      start: Position::new(start.0, start.1),
+      end: Position::new(end.0, end.1),
+    };
+    apply_change(
+      &mut text,
+      TextDocumentContentChangeEvent {
+        range: Some(range),
+        range_length: None,
+        text: new.to_owned(),
+      },
+    );
+    text
+  };
+  assert_eq!(change("val x = 3", (0, 4), (0, 5), "y"), "val y = 3");
+  assert_eq!(
+    change("val x = 3\nval y = x", (1, 8), (1, 9), "4"),
+    "val x = 3\nval y = 4"
+  );
+  assert_eq!(
+    change("val x = 3", (0, 9), (0, 9), "\nval y = x"),
+    "val x = 3\nval y = x"
+  );
+  assert_eq!(change("a\nb\nc", (0, 1), (2, 0), ""), "ac");
+  assert_eq!(change("ab\ncd", (0, 10), (1, 0), ""), "abcd");
+  assert_eq!(change("ab", (5, 0), (5, 0), "c"), "abc");
+  // é is one UTF-16 code unit but two bytes, 𝔸 is two UTF-16 code units and four bytes.
+  assert_eq!(change("\"é\" x", (0, 4), (0, 5), "y"), "\"é\" y");
+  assert_eq!(change("\"𝔸\" x", (0, 5), (0, 6), "y"), "\"𝔸\" y");
+  assert_eq!(change("\"𝔸\"", (0, 1), (0, 3), "b"), "\"b\"");
 }
@@ -0,0 +1,279 @@
+//! Document symbols, an outline of what a file declares.
+
+use crate::state::range;
+use lsp_types::{DocumentSymbol, SymbolKind};
+use millet_core::ast::{
+  DatBind, Dec, ExBindInner, Pat, SigExp, Spec, StrDec, StrExp, TopDec, TyBind,
+};
+use millet_core::intern::{StrRef, StrStore};
+use millet_core::line_index::LineIndex;
+use millet_core::loc::{Loc, Located};
+
+/// Returns the symbols declared by the top-level declarations of the file with lines `lines`.
+pub fn get(
+  store: &StrStore,
+  lines: &LineIndex,
+  top_decs: &[Located<TopDec<StrRef>>],
+) -> Vec<DocumentSymbol> {
+  let cx = Cx { store, lines };
+  let mut ret = Vec::new();
+  for top_dec in top_decs {
+    cx.top_dec(&mut ret, top_dec);
+  }
+  ret
+}
+
+struct Cx<'a> {
+  store: &'a StrStore,
+  lines: &'a LineIndex,
+}
+
+impl Cx<'_> {
+  /// Returns a symbol for `name`, whose whole declaration is at `loc`.
+  fn symbol(
+    &self,
+    name: Located<StrRef>,
+    kind: SymbolKind,
+    loc: Loc,
+    children: Vec<DocumentSymbol>,
+  ) -> DocumentSymbol {
+    DocumentSymbol {
+      name: self.store.get(name.val).to_owned(),
+      detail: None,
+      kind,
+      deprecated: None,
+      range: range(self.lines, loc),
+      selection_range: range(self.lines, name.loc),
+      children: if children.is_empty() {
+        None
+      } else {
+        Some(children)
+      },
+    }
+  }
+
+  fn top_dec(&self, ret: &mut Vec<DocumentSymbol>, top_dec: &Located<TopDec<StrRef>>) {
+    match &top_dec.val {
+      TopDec::StrDec(str_dec) => self.str_dec(ret, str_dec),
+      TopDec::SigDec(sig_binds) => {
+        for sig_bind in sig_binds {
+          let mut children = Vec::new();
+          self.sig_exp(&mut children, &sig_bind.exp);
+          let loc = sig_bind.id.loc.span(sig_bind.exp.loc);
+          ret.push(self.symbol(sig_bind.id, SymbolKind::Interface, loc, children));
+        }
+      }
+      TopDec::FunDec(fun_binds) => {
+        for fun_bind in fun_binds {
+          let mut children = Vec::new();
+          self.str_exp(&mut children, &fun_bind.str_exp);
+          let loc = fun_bind.fun_id.loc.span(fun_bind.str_exp.loc);
+          ret.push(self.symbol(fun_bind.fun_id, SymbolKind::Class, loc, children));
+        }
+      }
+    }
+  }
+
+  fn str_dec(&self, ret: &mut Vec<DocumentSymbol>, str_dec: &Located<StrDec<StrRef>>) {
+    match &str_dec.val {
+      StrDec::Dec(dec) => self.dec(ret, dec),
+      StrDec::Structure(str_binds) => {
+        for str_bind in str_binds {
+          let mut children = Vec::new();
+          self.str_exp(&mut children, &str_bind.exp);
+          let loc = str_bind.id.loc.span(str_bind.exp.loc);
+          ret.push(self.symbol(str_bind.id, SymbolKind::Module, loc, children));
+        }
+      }
+      StrDec::Local(fst, snd) => {
+        self.str_dec(ret, fst);
+        self.str_dec(ret, snd);
+      }
+      StrDec::Seq(str_decs) => {
+        for str_dec in str_decs {
+          self.str_dec(ret, str_dec);
+        }
+      }
+    }
+  }
+
+  fn str_exp(&self, ret: &mut Vec<DocumentSymbol>, str_exp: &Located<StrExp<StrRef>>) {
+    match &str_exp.val {
+      StrExp::Struct(str_dec) => self.str_dec(ret, str_dec),
+      StrExp::LongStrId(_) | StrExp::FunctorApp(..) => {}
+      StrExp::Ascription(str_exp, _, _) | StrExp::Let(_, str_exp) => self.str_exp(ret, str_exp),
+    }
+  }
+
+  fn sig_exp(&self, ret: &mut Vec<DocumentSymbol>, sig_exp: &Located<SigExp<StrRef>>) {
+    match &sig_exp.val {
+      SigExp::Sig(spec) => self.spec(ret, spec),
+      SigExp::SigId(_) => {}
+      SigExp::Where(sig_exp, _, _, _) => self.sig_exp(ret, sig_exp),
+    }
+  }
+
+  fn spec(&self, ret: &mut Vec<DocumentSymbol>, spec: &Located<Spec<StrRef>>) {
+    match &spec.val {
+      Spec::Val(val_descs) => {
+        for val_desc in val_descs {
+          let loc = val_desc.vid.loc.span(val_desc.ty.loc);
+          ret.push(self.symbol(val_desc.vid, SymbolKind::Variable, loc, Vec::new()));
+        }
+      }
+      Spec::Type(ty_descs, _) => {
+        for ty_desc in ty_descs {
+          let loc = ty_desc.ty_con.loc;
+          ret.push(self.symbol(ty_desc.ty_con, SymbolKind::TypeParameter, loc, Vec::new()));
+        }
+      }
+      Spec::Datatype(dat_binds) => self.dat_binds(ret, dat_binds),
+      Spec::DatatypeCopy(ty_con, long) => {
+        let loc = ty_con.loc.span(long.loc());
+        ret.push(self.symbol(*ty_con, SymbolKind::Enum, loc, Vec::new()));
+      }
+      Spec::Exception(ex_descs) => {
+        for ex_desc in ex_descs {
+          let loc = match &ex_desc.ty {
+            None => ex_desc.vid.loc,
+            Some(ty) => ex_desc.vid.loc.span(ty.loc),
+          };
+          ret.push(self.symbol(ex_desc.vid, SymbolKind::Constructor, loc, Vec::new()));
+        }
+      }
+      Spec::Structure(str_descs) => {
+        for str_desc in str_descs {
+          let mut children = Vec::new();
+          self.sig_exp(&mut children, &str_desc.exp);
+          let loc = str_desc.str_id.loc.span(str_desc.exp.loc);
+          ret.push(self.symbol(str_desc.str_id, SymbolKind::Module, loc, children));
+        }
+      }
+      Spec::Include(sig_exp) => self.sig_exp(ret, sig_exp),
+      Spec::Seq(specs) => {
+        for spec in specs {
+          self.spec(ret, spec);
+        }
+      }
+      Spec::Sharing(spec, _, _) => self.spec(ret, spec),
+    }
+  }
+
+  fn dec(&self, ret: &mut Vec<DocumentSymbol>, dec: &Located<Dec<StrRef>>) {
+    match &dec.val {
+      Dec::Val(_, val_binds) => {
+        for val_bind in val_binds {
+          let loc = val_bind.pat.loc.span(val_bind.exp.loc);
+          let mut names = Vec::new();
+          pat_names(&mut names, &val_bind.pat);
+          for name in names {
+            ret.push(self.symbol(name, SymbolKind::Variable, loc, Vec::new()));
+          }
+        }
+      }
+      Dec::Fun(_, fval_binds) => {
+        for fval_bind in fval_binds {
+          let first = fval_bind.cases.first().unwrap();
+          let last = fval_bind.cases.last().unwrap();
+          let loc = first.vid.loc.span(last.body.loc);
+          ret.push(self.symbol(first.vid, SymbolKind::Function, loc, Vec::new()));
+        }
+      }
+      Dec::Type(ty_binds) => self.ty_binds(ret, ty_binds),
+      Dec::Datatype(dat_binds, ty_binds) => {
+        self.dat_binds(ret, dat_binds);
+        self.ty_binds(ret, ty_binds);
+      }
+      Dec::DatatypeCopy(ty_con, long) => {
+        let loc = ty_con.loc.span(long.loc());
+        ret.push(self.symbol(*ty_con, SymbolKind::Enum, loc, Vec::new()));
+      }
+      Dec::Abstype(dat_binds, ty_binds, dec) => {
+        self.dat_binds(ret, dat_binds);
+        self.ty_binds(ret, ty_binds);
+        self.dec(ret, dec);
+      }
+      Dec::Exception(ex_binds) => {
+        for ex_bind in ex_binds {
+          let loc = match &ex_bind.inner {
+            ExBindInner::Ty(None) => ex_bind.vid.loc,
+            ExBindInner::Ty(Some(ty)) => ex_bind.vid.loc.span(ty.loc),
+            ExBindInner::Long(long) => ex_bind.vid.loc.span(long.loc()),
+          };
+          ret.push(self.symbol(ex_bind.vid, SymbolKind::Constructor, loc, Vec::new()));
+        }
+      }
+      Dec::Local(fst, snd) => {
+        self.dec(ret, fst);
+        self.dec(ret, snd);
+      }
+      Dec::Seq(decs) => {
+        for dec in decs {
+          self.dec(ret, dec);
+        }
+      }
+      Dec::Open(_) | Dec::Infix(..) | Dec::Infixr(..) | Dec::Nonfix(..) => {}
+    }
+  }
+
+  fn dat_binds(&self, ret: &mut Vec<DocumentSymbol>, dat_binds: &[DatBind<StrRef>]) {
+    for dat_bind in dat_binds {
+      let mut loc = dat_bind.ty_con.loc;
+      let mut children = Vec::with_capacity(dat_bind.cons.len());
+      for con_bind in dat_bind.cons.iter() {
+        let con_loc = match &con_bind.ty {
+          None => con_bind.vid.loc,
+          Some(ty) => con_bind.vid.loc.span(ty.loc),
+        };
+        loc = loc.span(con_loc);
+        children.push(self.symbol(con_bind.vid, SymbolKind::EnumMember, con_loc, Vec::new()));
+      }
+      ret.push(self.symbol(dat_bind.ty_con, SymbolKind::Enum, loc, children));
+    }
+  }
+
+  fn ty_binds(&self, ret: &mut Vec<DocumentSymbol>, ty_binds: &[TyBind<StrRef>]) {
+    for ty_bind in ty_binds {
+      let loc = ty_bind.ty_con.loc.span(ty_bind.ty.loc);
+      ret.push(self.symbol(ty_bind.ty_con, SymbolKind::TypeParameter, loc, Vec::new()));
+    }
+  }
+}
+
+/// Pushes the names of the variables the pattern binds onto `ret`. Since this doesn't know which
+/// names are constructors, unqualified constructors with no argument are included too.
+fn pat_names(ret: &mut Vec<Located<StrRef>>, pat: &Located<Pat<StrRef>>) {
+  match &pat.val {
+    Pat::Wildcard
+    | Pat::DecInt(_)
+    | Pat::HexInt(_)
+    | Pat::DecWord(_)
+    | Pat::HexWord(_)
+    | Pat::String(_)
+    | Pat::Char(_) => {}
+    Pat::LongVid(long) => {
+      if long.structures.is_empty() {
+        ret.push(long.last);
+      }
+    }
+    Pat::Record(rows, _) => {
+      for row in rows {
+        pat_names(ret, &row.val);
+      }
+    }
+    Pat::Tuple(pats) | Pat::List(pats) => {
+      for pat in pats {
+        pat_names(ret, pat);
+      }
+    }
+    Pat::Ctor(_, pat) | Pat::Typed(pat, _) => pat_names(ret, pat),
+    Pat::InfixCtor(lhs, _, rhs) => {
+      pat_names(ret, lhs);
+      pat_names(ret, rhs);
+    }
+    Pat::As(vid, _, pat) => {
+      ret.push(*vid);
+      pat_names(ret, pat);
+    }
+  }
+}
@@ -0,0 +1,418 @@
+//! Tests that drive the server with transcripts of JSON-RPC messages.
+//!
+//! Each non-empty line of a transcript is either `-->` followed by a message to the server, or
+//! `<--` followed by a message we expect from the server. The messages from the server are compared
+//! with the expected ones after the whole transcript is run. After each message to the server, we
+//! wait for all the work it started in the background to finish.
+//!
+//! A line may also be `..>` followed by a message to the server. Then we wait for the response or
+//! diagnostics it causes to be worked out in the background, but only pass them back to the server
+//! after the next message, as if that message arrived first.
+
+use crate::comm::Incoming;
+use crate::state::{Action, Done, State};
+use lsp_types::Url;
+use serde_json::Value;
+use std::path::Path;
+
+/// Checking a file containing this panics.
+pub const PANIC: &str = "(* millet-ls: panic *)";
+
+/// Runs the transcript on a server that got the initialize request.
+fn check(transcript: &str) {
+  let init = r#"
+--> {"jsonrpc":"2.0","id":0,"method":"initialize","params":{"processId":null,"rootUri":null,"capabilities":{}}}
+--> {"jsonrpc":"2.0","method":"initialized","params":{}}
+"#;
+  let (mut st, r_done) = new_state();
+  run(&mut st, &r_done, init);
+  assert_eq!(run(&mut st, &r_done, transcript), expected(transcript));
+}
+
+/// Runs the transcript on a server that got the initialize request, with the workspace root at the
+/// directory `dir` in the `tests` directory at the root of the repository, and `options` as the
+/// initialization options. `{root}` in the transcript is replaced with the URI of the root.
+fn check_in(dir: &str, options: Value, transcript: &str) {
+  let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+    .parent()
+    .and_then(Path::parent)
+    .unwrap()
+    .join("tests")
+    .join(dir);
+  let root = Url::from_directory_path(root).unwrap();
+  let init = serde_json::json!({
+    "jsonrpc": "2.0",
+    "id": 0,
+    "method": "initialize",
+    "params": {"processId": null, "rootUri": root, "capabilities": {}, "initializationOptions": options},
+  });
+  let init = format!(
+    "--> {}\n--> {}",
+    init, r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#
+  );
+  let transcript = transcript.replace("{root}", root.as_str().trim_end_matches('/'));
+  let (mut st, r_done) = new_state();
+  run(&mut st, &r_done, &init);
+  assert_eq!(run(&mut st, &r_done, &transcript), expected(&transcript));
+}
+
+/// Runs the transcript on a server that didn't get the initialize request.
+fn check_uninitialized(transcript: &str) {
+  let (mut st, r_done) = new_state();
+  assert_eq!(run(&mut st, &r_done, transcript), expected(transcript));
+}
+
+fn new_state() -> (State, crossbeam_channel::Receiver<Done>) {
+  let (s_done, r_done) = crossbeam_channel::unbounded();
+  (State::new(s_done), r_done)
+}
+
+/// Sends the messages to the server, and returns the messages it sent back.
+fn run(st: &mut State, r_done: &crossbeam_channel::Receiver<Done>, transcript: &str) -> Vec<Value> {
+  let mut ret = Vec::new();
+  let mut held = Vec::new();
+  for line in transcript.lines() {
+    let (msg, hold) = match (line.strip_prefix("-->"), line.strip_prefix("..>")) {
+      (Some(x), _) => (x.trim(), false),
+      (_, Some(x)) => (x.trim(), true),
+      (None, None) => continue,
+    };
+    if let Some(action) = Incoming::try_parse(msg.as_bytes()).and_then(|x| st.handle(x)) {
+      push(&mut ret, action);
+    }
+    if hold {
+      loop {
+        let done = r_done.recv().unwrap();
+        let finished = matches!(done, Done::Response(_) | Done::Diagnostics(_));
+        held.push(done);
+        if finished {
+          break;
+        }
+      }
+      continue;
+    }
+    for done in held.drain(..) {
+      if let Some(action) = st.handle_done(done) {
+        push(&mut ret, action);
+      }
+    }
+    while !st.is_idle() {
+      if let Some(action) = st.handle_done(r_done.recv().unwrap()) {
+        push(&mut ret, action);
+      }
+    }
+    while let Ok(done) = r_done.try_recv() {
+      if let Some(action) = st.handle_done(done) {
+        push(&mut ret, action);
+      }
+    }
+  }
+  ret
+}
+
+fn push(ret: &mut Vec<Value>, action: Action) {
+  match action {
+    Action::Exit(_) => panic!("unexpected exit"),
+    Action::Respond(xs) => {
+      for x in xs {
+        ret.push(serde_json::from_slice(&x.into_vec().unwrap()).unwrap());
+      }
+    }
+  }
+}
+
+/// Returns the messages the transcript expects from the server.
+fn expected(transcript: &str) -> Vec<Value> {
+  transcript
+    .lines()
+    .filter_map(|line| line.strip_prefix("<--"))
+    .map(|msg| serde_json::from_str(msg).unwrap())
+    .collect()
+}
+
+#[test]
+fn test_method_not_found() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/frobnicate","params":{}}
+<-- {"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"unknown method: textDocument/frobnicate"}}
+--> {"jsonrpc":"2.0","method":"$/frobnicate","params":{}}
+--> {"jsonrpc":"2.0","id":"two","method":"shutdown"}
+<-- {"jsonrpc":"2.0","id":"two","result":null}
+"#,
+  );
+}
+
+#[test]
+fn test_invalid_params() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{"position":{"line":0,"character":0}}}
+<-- {"jsonrpc":"2.0","id":1,"error":{"code":-32602,"message":"missing field `textDocument`"}}
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{}}
+"#,
+  );
+}
+
+#[test]
+fn test_invalid_message() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","id":1,"method":
+<-- {"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"EOF while parsing a value at line 1 column 33"}}
+--> {"jsonrpc":"1.0","id":2,"method":"shutdown"}
+<-- {"jsonrpc":"2.0","id":2,"error":{"code":-32600,"message":"jsonrpc was not 2.0"}}
+--> {"jsonrpc":"2.0","id":3}
+<-- {"jsonrpc":"2.0","id":3,"error":{"code":-32600,"message":"no method"}}
+--> {"jsonrpc":"2.0","id":4,"result":null}
+"#,
+  );
+}
+
+#[test]
+fn test_not_initialized() {
+  check_uninitialized(
+    r#"
+--> {"jsonrpc":"2.0","id":1,"method":"shutdown"}
+<-- {"jsonrpc":"2.0","id":1,"error":{"code":-32002,"message":"server not initialized"}}
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"val _ = 3"}}}
+"#,
+  );
+}
+
+#[test]
+fn test_diagnostics_and_hover() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"val x = 3\nval y : string = x"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[{"range":{"start":{"line":1,"character":0},"end":{"line":1,"character":18}},"severity":1,"source":"millet-ls","message":"mismatched types: expected string, found int"}]}}
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.sml"},"position":{"line":0,"character":4}}}
+<-- {"jsonrpc":"2.0","id":1,"result":{"contents":{"language":"sml","value":"int"},"range":{"start":{"line":0,"character":4},"end":{"line":0,"character":5}}}}
+--> {"jsonrpc":"2.0","method":"textDocument/didClose","params":{"textDocument":{"uri":"file:///a.sml"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","diagnostics":[]}}
+"#,
+  );
+}
+
+#[test]
+fn test_config() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"workspace/didChangeConfiguration","params":{"settings":{"millet":{"lints":{"unreachablePattern":false},"nonExhaustive":"error","maxDiagnostics":1}}}}
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"fun f 0 = 1 | f 0 = 2 | f _ = 3\nval g = fn true => 1\nval h = fn 0 => 1"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[{"range":{"start":{"line":1,"character":8},"end":{"line":1,"character":20}},"severity":1,"source":"millet-ls","message":"non-exhaustive match: missing false"}]}}
+--> {"jsonrpc":"2.0","method":"workspace/didChangeConfiguration","params":{"settings":{"millet":{"lints":null,"nonExhaustive":null,"maxDiagnostics":null}}}}
+--> {"jsonrpc":"2.0","method":"textDocument/didSave","params":{"textDocument":{"uri":"file:///a.sml"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[{"range":{"start":{"line":0,"character":16},"end":{"line":0,"character":17}},"severity":2,"source":"millet-ls","message":"unreachable pattern"},{"range":{"start":{"line":1,"character":8},"end":{"line":1,"character":20}},"severity":2,"source":"millet-ls","message":"non-exhaustive match: missing false"},{"range":{"start":{"line":2,"character":8},"end":{"line":2,"character":17}},"severity":2,"source":"millet-ls","message":"non-exhaustive match: missing _"}]}}
+"#,
+  );
+}
+
+#[test]
+fn test_code_action() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"val x =\n  case 3 < 4 of\n    true => 1"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[{"range":{"start":{"line":1,"character":2},"end":{"line":2,"character":13}},"severity":2,"source":"millet-ls","message":"non-exhaustive match: missing false"}]}}
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/codeAction","params":{"textDocument":{"uri":"file:///a.sml"},"range":{"start":{"line":2,"character":4},"end":{"line":2,"character":4}},"context":{"diagnostics":[{"range":{"start":{"line":1,"character":2},"end":{"line":2,"character":13}},"severity":2,"source":"millet-ls","message":"non-exhaustive match: missing false"}]}}}
+<-- {"jsonrpc":"2.0","id":1,"result":[{"title":"Add missing arm","kind":"quickfix","diagnostics":[{"range":{"start":{"line":1,"character":2},"end":{"line":2,"character":13}},"severity":2,"source":"millet-ls","message":"non-exhaustive match: missing false"}],"edit":{"changes":{"file:///a.sml":[{"range":{"start":{"line":2,"character":13},"end":{"line":2,"character":13}},"newText":"\n  | false => raise Fail \"unimplemented\""}]}}}]}
+--> {"jsonrpc":"2.0","id":2,"method":"textDocument/codeAction","params":{"textDocument":{"uri":"file:///a.sml"},"range":{"start":{"line":0,"character":4},"end":{"line":0,"character":4}},"context":{"diagnostics":[]}}}
+<-- {"jsonrpc":"2.0","id":2,"result":null}
+"#,
+  );
+}
+
+#[test]
+fn test_save() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"val x = 3"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[]}}
+..> {"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.sml"},"position":{"line":0,"character":4}}}
+--> {"jsonrpc":"2.0","method":"textDocument/didSave","params":{"textDocument":{"uri":"file:///a.sml"}}}
+<-- {"jsonrpc":"2.0","id":1,"result":{"contents":{"language":"sml","value":"int"},"range":{"start":{"line":0,"character":4},"end":{"line":0,"character":5}}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[]}}
+"#,
+  );
+}
+
+#[test]
+fn test_config_merge() {
+  check_in(
+    "",
+    serde_json::json!({"manifestPath": "cm/sources.cm"}),
+    r#"
+--> {"jsonrpc":"2.0","method":"workspace/didChangeConfiguration","params":{"settings":{"maxDiagnostics":5}}}
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"{root}/cm/main.sml","languageId":"sml","version":1,"text":"val s = Stack.push (1, Stack.push (2, Stack.empty))"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"{root}/cm/lib/stack.sig","diagnostics":[]}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"{root}/cm/lib/stack.sml","diagnostics":[]}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"{root}/cm/main.sml","version":1,"diagnostics":[]}}
+"#,
+  );
+}
+
+#[test]
+fn test_definition() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"val x = 3\nval y = x"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[]}}
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.sml"},"position":{"line":1,"character":8}}}
+<-- {"jsonrpc":"2.0","id":1,"result":{"uri":"file:///a.sml","range":{"start":{"line":0,"character":4},"end":{"line":0,"character":5}}}}
+--> {"jsonrpc":"2.0","id":2,"method":"textDocument/definition","params":{"textDocument":{"uri":"file:///a.sml"},"position":{"line":1,"character":4}}}
+<-- {"jsonrpc":"2.0","id":2,"result":null}
+"#,
+  );
+}
+
+#[test]
+fn test_rename() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"val x = 3\nval y = x + x"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[]}}
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/rename","params":{"textDocument":{"uri":"file:///a.sml"},"position":{"line":0,"character":4},"newName":"z"}}
+<-- {"jsonrpc":"2.0","id":1,"result":{"changes":{"file:///a.sml":[{"range":{"start":{"line":0,"character":4},"end":{"line":0,"character":5}},"newText":"z"},{"range":{"start":{"line":1,"character":8},"end":{"line":1,"character":9}},"newText":"z"},{"range":{"start":{"line":1,"character":12},"end":{"line":1,"character":13}},"newText":"z"}]}}}
+"#,
+  );
+}
+
+#[test]
+fn test_rename_collision() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"val x = 3\nval y = x"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[]}}
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/rename","params":{"textDocument":{"uri":"file:///a.sml"},"position":{"line":1,"character":8},"newName":"y"}}
+<-- {"jsonrpc":"2.0","id":1,"error":{"code":-32600,"message":"renaming to `y` would collide with an existing name"}}
+"#,
+  );
+}
+
+#[test]
+fn test_rename_shadowing() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"val x = 3\nfun f y = x + y"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[]}}
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/rename","params":{"textDocument":{"uri":"file:///a.sml"},"position":{"line":0,"character":4},"newName":"y"}}
+<-- {"jsonrpc":"2.0","id":1,"error":{"code":-32600,"message":"renaming to `y` would collide with an existing name"}}
+"#,
+  );
+}
+
+#[test]
+fn test_references() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"val x = 3\nval y = x + x"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[]}}
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/references","params":{"textDocument":{"uri":"file:///a.sml"},"position":{"line":1,"character":8},"context":{"includeDeclaration":true}}}
+<-- {"jsonrpc":"2.0","id":1,"result":[{"uri":"file:///a.sml","range":{"start":{"line":0,"character":4},"end":{"line":0,"character":5}}},{"uri":"file:///a.sml","range":{"start":{"line":1,"character":8},"end":{"line":1,"character":9}}},{"uri":"file:///a.sml","range":{"start":{"line":1,"character":12},"end":{"line":1,"character":13}}}]}
+--> {"jsonrpc":"2.0","id":2,"method":"textDocument/references","params":{"textDocument":{"uri":"file:///a.sml"},"position":{"line":1,"character":8},"context":{"includeDeclaration":false}}}
+<-- {"jsonrpc":"2.0","id":2,"result":[{"uri":"file:///a.sml","range":{"start":{"line":1,"character":8},"end":{"line":1,"character":9}}},{"uri":"file:///a.sml","range":{"start":{"line":1,"character":12},"end":{"line":1,"character":13}}}]}
+"#,
+  );
+}
+
+#[test]
+fn test_completion() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"structure A = struct val foo = 3 end\nval _ = A.foo"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[]}}
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/completion","params":{"textDocument":{"uri":"file:///a.sml"},"position":{"line":1,"character":10}}}
+<-- {"jsonrpc":"2.0","id":1,"result":[{"kind":12,"label":"foo","detail":"int"}]}
+"#,
+  );
+}
+
+#[test]
+fn test_symbols() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"structure A = struct val foo = 3 end\nfun bar x = x"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[]}}
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///a.sml"}}}
+<-- {"jsonrpc":"2.0","id":1,"result":[{"range":{"start":{"line":0,"character":10},"end":{"line":0,"character":36}},"name":"A","kind":2,"selectionRange":{"start":{"line":0,"character":10},"end":{"line":0,"character":11}},"children":[{"range":{"start":{"line":0,"character":25},"end":{"line":0,"character":32}},"name":"foo","kind":13,"selectionRange":{"start":{"line":0,"character":25},"end":{"line":0,"character":28}}}]},{"range":{"start":{"line":1,"character":4},"end":{"line":1,"character":13}},"name":"bar","kind":12,"selectionRange":{"start":{"line":1,"character":4},"end":{"line":1,"character":7}}}]}
+--> {"jsonrpc":"2.0","id":2,"method":"workspace/symbol","params":{"query":"foo"}}
+<-- {"jsonrpc":"2.0","id":2,"result":[{"name":"foo","kind":13,"containerName":"A","location":{"uri":"file:///a.sml","range":{"start":{"line":0,"character":25},"end":{"line":0,"character":28}}}}]}
+"#,
+  );
+}
+
+#[test]
+fn test_recheck() {
+  check_in(
+    "cm",
+    Value::Null,
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"{root}/lib/stack.sml","languageId":"sml","version":1,"text":"structure Stack :> STACK = struct\n  type t = int list\n  val empty = []\n  fun push (x, xs) = x :: xs\nend\n"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"{root}/lib/stack.sig","diagnostics":[]}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"{root}/lib/stack.sml","version":1,"diagnostics":[]}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"{root}/main.sml","diagnostics":[]}}
+--> {"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"{root}/lib/stack.sml","version":2},"contentChanges":[{"text":"structure Stk :> STACK = struct\n  type t = int list\n  val empty = []\n  fun push (x, xs) = x :: xs\nend\n"}]}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"{root}/lib/stack.sml","version":2,"diagnostics":[]}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"{root}/main.sml","diagnostics":[{"range":{"start":{"line":0,"character":8},"end":{"line":0,"character":13}},"severity":1,"source":"millet-ls","message":"undefined structure: Stack"},{"range":{"start":{"line":0,"character":23},"end":{"line":0,"character":28}},"severity":1,"source":"millet-ls","message":"undefined structure: Stack"},{"range":{"start":{"line":0,"character":38},"end":{"line":0,"character":43}},"severity":1,"source":"millet-ls","message":"undefined structure: Stack"}]}}
+"#,
+  );
+}
+
+#[test]
+fn test_cancel() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"val x = 3"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[]}}
+..> {"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.sml"},"position":{"line":0,"character":4}}}
+--> {"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///a.sml","version":2},"contentChanges":[{"text":"val y = 4"}]}}
+<-- {"jsonrpc":"2.0","id":1,"error":{"code":-32801,"message":"content modified"}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":2,"diagnostics":[]}}
+"#,
+  );
+}
+
+#[test]
+fn test_deep_nesting() {
+  let text = format!("val x = {}1{}", "(".repeat(300), ")".repeat(300));
+  check(&format!(
+    r#"
+--> {{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"{}"}}}}}}
+<-- {{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{{"uri":"file:///a.sml","version":1,"diagnostics":[]}}}}
+"#,
+    text
+  ));
+}
+
+#[test]
+fn test_panic() {
+  check(
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sml","languageId":"sml","version":1,"text":"(* millet-ls: panic *)"}}}
+<-- {"jsonrpc":"2.0","method":"window/logMessage","params":{"type":1,"message":"internal error when checking file:///a.sml: asked to panic"}}
+<-- {"jsonrpc":"2.0","method":"window/showMessage","params":{"type":1,"message":"millet-ls: internal error when checking file:///a.sml: asked to panic"}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///a.sml","version":1,"diagnostics":[{"range":{"start":{"line":0,"character":0},"end":{"line":0,"character":0}},"severity":1,"source":"millet-ls","message":"internal error: asked to panic"}]}}
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.sml"},"position":{"line":0,"character":0}}}
+<-- {"jsonrpc":"2.0","method":"window/logMessage","params":{"type":1,"message":"internal error: asked to panic"}}
+<-- {"jsonrpc":"2.0","id":1,"error":{"code":-32603,"message":"internal error: asked to panic"}}
+--> {"jsonrpc":"2.0","id":2,"method":"shutdown"}
+<-- {"jsonrpc":"2.0","id":2,"result":null}
+"#,
+  );
+}
+
+#[test]
+fn test_encoded_uri() {
+  check_in(
+    "cm",
+    Value::Null,
+    r#"
+--> {"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"{root}/%6Dain.sml","languageId":"sml","version":1,"text":"val s = Stack.push (1, Stack.empty)"}}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"{root}/lib/stack.sig","diagnostics":[]}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"{root}/lib/stack.sml","diagnostics":[]}}
+<-- {"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"{root}/%6Dain.sml","version":1,"diagnostics":[]}}
+--> {"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{"textDocument":{"uri":"{root}/%6Dain.sml"},"position":{"line":0,"character":4}}}
+<-- {"jsonrpc":"2.0","id":1,"result":{"contents":{"language":"sml","value":"t"},"range":{"start":{"line":0,"character":4},"end":{"line":0,"character":5}}}}
+"#,
+  );
+}
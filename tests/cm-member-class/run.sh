@@ -0,0 +1,3 @@
+"$MILLET" --quiet sources.cm >out.tmp
+if [ -s out.tmp ]; then exit 1; fi
+rm out.tmp
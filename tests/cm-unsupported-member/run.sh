@@ -0,0 +1,4 @@
+"$MILLET" sources.cm >out.tmp
+diff out.txt out.tmp
+if "$MILLET" --deny-warnings sources.cm >/dev/null; then exit 1; fi
+rm out.tmp
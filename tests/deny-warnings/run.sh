@@ -0,0 +1,3 @@
+if "$MILLET" --deny-warnings a.sml >out.tmp; then exit 1; fi
+diff out.txt out.tmp
+rm out.tmp